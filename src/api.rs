@@ -1,26 +1,27 @@
-use std::{fs, path::MAIN_SEPARATOR_STR};
+use tokio::fs;
 
 use crate::{
-    core::server::Context,
+    core::{files::FileResolver, server::Context, static_files::serve_file},
     types::{request::HttpRequest, response::HttpResponse, status::StatusCode},
 };
 
-pub fn index(_req: HttpRequest, _ctx: &Context) -> HttpResponse {
+pub async fn index(_req: HttpRequest, _ctx: Context) -> HttpResponse {
     HttpResponse::builder().status_code(StatusCode::Ok).build()
 }
 
-pub fn user_agent(req: HttpRequest, _ctx: &Context) -> HttpResponse {
-    let user_agent = req
-        .headers
-        .get("User-Agent")
-        .expect("No user agent header found");
-    HttpResponse::builder()
-        .status_code(StatusCode::Ok)
-        .plain_text(user_agent.to_string())
-        .build()
+pub async fn user_agent(req: HttpRequest, _ctx: Context) -> HttpResponse {
+    match req.headers.get("User-Agent") {
+        Some(user_agent) => HttpResponse::builder()
+            .status_code(StatusCode::Ok)
+            .plain_text(user_agent.to_string())
+            .build(),
+        None => HttpResponse::builder()
+            .status_code(StatusCode::BadRequest)
+            .build(),
+    }
 }
 
-pub fn get_str(req: HttpRequest, _ctx: &Context) -> HttpResponse {
+pub async fn get_str(req: HttpRequest, _ctx: Context) -> HttpResponse {
     let str = req.path_params.get("str").unwrap().trim().to_string();
     HttpResponse::builder()
         .status_code(StatusCode::Ok)
@@ -28,36 +29,54 @@ pub fn get_str(req: HttpRequest, _ctx: &Context) -> HttpResponse {
         .build()
 }
 
-pub fn get_file(req: HttpRequest, ctx: &Context) -> HttpResponse {
+pub async fn get_file(req: HttpRequest, ctx: Context) -> HttpResponse {
     let filename = req.path_params.get("filename").unwrap().trim();
     println!("filename: {}", filename);
-    let filepath = ctx.workdir.to_string() + MAIN_SEPARATOR_STR + filename;
-    if let Ok(content) = fs::read(filepath.clone()) {
-        println!("succesfully read file: {}", &filepath);
-        HttpResponse::builder()
-            .status_code(StatusCode::Ok)
-            .file(content)
-            .build()
-    } else {
-        println!("Failed to read file: {}", filepath);
-        HttpResponse::builder()
-            .status_code(StatusCode::NotFound)
-            .build()
-    }
+    let resolver = match FileResolver::new(&ctx.workdir).await {
+        Ok(resolver) => resolver,
+        Err(err) => {
+            return HttpResponse::builder()
+                .status_code(err.status_code())
+                .build();
+        }
+    };
+    let filepath = match resolver.resolve(filename).await {
+        Ok(filepath) => filepath,
+        Err(err) => {
+            println!("Refused to read file {}: {}", filename, err);
+            return HttpResponse::builder()
+                .status_code(err.status_code())
+                .build();
+        }
+    };
+    println!("serving file: {}", filepath.display());
+    serve_file(&req, ctx.mime_types(), &resolver, filename, &filepath).await
 }
 
-pub fn create_file(req: HttpRequest, ctx: &Context) -> HttpResponse {
+pub async fn create_file(req: HttpRequest, ctx: Context) -> HttpResponse {
     let filename = req.path_params.get("filename").unwrap().trim();
     println!("filename: {}", filename);
-    let filepath = ctx.workdir.to_string() + MAIN_SEPARATOR_STR + filename;
-    if let Ok(()) = fs::write(filepath.clone(), req.body.as_bytes()) {
-        println!("succesfully read file: {}", &filepath);
+    let resolved = match FileResolver::new(&ctx.workdir).await {
+        Ok(resolver) => resolver.resolve_new(filename).await,
+        Err(err) => Err(err),
+    };
+    let filepath = match resolved {
+        Ok(filepath) => filepath,
+        Err(err) => {
+            println!("Refused to write file {}: {}", filename, err);
+            return HttpResponse::builder()
+                .status_code(err.status_code())
+                .build();
+        }
+    };
+    if let Ok(()) = fs::write(&filepath, &req.body).await {
+        println!("succesfully wrote file: {}", filepath.display());
         HttpResponse::builder()
             .status_code(StatusCode::Created)
             .header("Content-Type", "application/octet-stream")
             .build()
     } else {
-        println!("Failed to read file: {}", filepath);
+        println!("Failed to write file: {}", filepath.display());
         HttpResponse::builder()
             .status_code(StatusCode::NotFound)
             .build()
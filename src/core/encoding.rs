@@ -0,0 +1,375 @@
+use std::{fmt, io, sync::Arc};
+
+use crate::types::{headers::parse_weighted, response::HttpResponse};
+
+pub type SharedEncoder = Arc<dyn Encoder>;
+
+/// A content coding applied to response bodies, e.g. gzip.
+///
+/// Encoders are registered under the token clients use in `Accept-Encoding`, see
+/// [`Encodings::add`]. Implement it to offer codings that are not built in.
+pub trait Encoder: Send + Sync + 'static {
+    fn encode(&self, data: &[u8], level: CompressionLevel) -> io::Result<Vec<u8>>;
+}
+
+/// How hard encoders try to shrink bodies, mapped onto the scale of each codec.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionLevel {
+    Fastest,
+    #[default]
+    Default,
+    Best,
+    /// A level on the codec's own scale, capped at its maximum
+    Precise(u32),
+}
+
+impl CompressionLevel {
+    /// The level on a codec scale going up to `max`, given its fastest, default and
+    /// best settings.
+    pub fn on_scale(self, fastest: u32, default: u32, best: u32, max: u32) -> u32 {
+        match self {
+            CompressionLevel::Fastest => fastest,
+            CompressionLevel::Default => default,
+            CompressionLevel::Best => best,
+            CompressionLevel::Precise(level) => level.min(max),
+        }
+    }
+}
+
+#[cfg(feature = "gzip")]
+pub struct Gzip;
+
+#[cfg(feature = "gzip")]
+impl Encoder for Gzip {
+    fn encode(&self, data: &[u8], level: CompressionLevel) -> io::Result<Vec<u8>> {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+
+        let level = Compression::new(level.on_scale(1, 6, 9, 9));
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+}
+
+/// The `deflate` coding, which is zlib wrapped deflate data (RFC 9110, section 8.4.1.2).
+#[cfg(feature = "deflate")]
+pub struct Deflate;
+
+#[cfg(feature = "deflate")]
+impl Encoder for Deflate {
+    fn encode(&self, data: &[u8], level: CompressionLevel) -> io::Result<Vec<u8>> {
+        use flate2::{Compression, write::ZlibEncoder};
+        use std::io::Write;
+
+        let level = Compression::new(level.on_scale(1, 6, 9, 9));
+        let mut encoder = ZlibEncoder::new(Vec::new(), level);
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+}
+
+#[cfg(feature = "brotli")]
+pub struct Brotli;
+
+#[cfg(feature = "brotli")]
+impl Encoder for Brotli {
+    fn encode(&self, data: &[u8], level: CompressionLevel) -> io::Result<Vec<u8>> {
+        use std::io::Write;
+
+        // quality 5 keeps on-the-fly compression cheap, 11 is meant for static assets
+        let quality = level.on_scale(1, 5, 11, 11);
+        let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, quality, 22);
+        encoder.write_all(data)?;
+        encoder.flush()?;
+        Ok(encoder.into_inner())
+    }
+}
+
+#[cfg(feature = "zstd")]
+pub struct Zstd;
+
+#[cfg(feature = "zstd")]
+impl Encoder for Zstd {
+    fn encode(&self, data: &[u8], level: CompressionLevel) -> io::Result<Vec<u8>> {
+        // levels above 19 need much more memory to decode, keep them opt-in
+        let level = level.on_scale(1, 3, 19, 22).max(1);
+        zstd::bulk::compress(data, level as i32)
+    }
+}
+
+/// The content codings a server can apply, in order of preference.
+///
+/// By default it holds the built-in encoders enabled through cargo features:
+/// `br`, `zstd`, `gzip` and `deflate`.
+#[derive(Clone)]
+pub struct Encodings {
+    encoders: Vec<(String, SharedEncoder)>,
+}
+
+impl Encodings {
+    /// An empty set, which never compresses.
+    pub fn new() -> Self {
+        Self {
+            encoders: Vec::new(),
+        }
+    }
+
+    /// Registers `encoder` under the coding `name`, replacing any encoder with that
+    /// name. New codings are preferred less than the ones registered before.
+    pub fn add(&mut self, name: &str, encoder: impl Encoder) {
+        let name = name.to_ascii_lowercase();
+        let encoder: SharedEncoder = Arc::new(encoder);
+        match self.encoders.iter_mut().find(|(known, _)| *known == name) {
+            Some((_, known)) => *known = encoder,
+            None => self.encoders.push((name, encoder)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.encoders
+            .retain(|(known, _)| !known.eq_ignore_ascii_case(name));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.encoders.is_empty()
+    }
+
+    /// Picks the coding to apply from the `Accept-Encoding` field values of a request,
+    /// see [`negotiate_coding`]. Returns `None` when the body should be sent as is.
+    pub fn negotiate<'a>(
+        &self,
+        accept_encoding: impl Iterator<Item = &'a str>,
+    ) -> Option<(&str, &SharedEncoder)> {
+        let names = self.encoders.iter().map(|(name, _)| name.as_str());
+        let chosen = negotiate_coding(accept_encoding, names)?;
+        self.encoders
+            .iter()
+            .find(|(name, _)| name == chosen)
+            .map(|(name, encoder)| (name.as_str(), encoder))
+    }
+}
+
+/// Picks among the `available` content codings, listed by preference, the one to
+/// apply given the `Accept-Encoding` field values of a request, following their
+/// q-values (RFC 9110, section 12.5.3). Returns `None` when the body should be sent
+/// as is.
+pub fn negotiate_coding<'a, 'b>(
+    accept_encoding: impl Iterator<Item = &'a str>,
+    available: impl IntoIterator<Item = &'b str>,
+) -> Option<&'b str> {
+    let accepted: Vec<(String, f32)> = accept_encoding
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_coding)
+        .collect();
+    let weight = |name: &str| {
+        accepted
+            .iter()
+            .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+            .or_else(|| accepted.iter().find(|(coding, _)| coding == "*"))
+            .map(|(_, q)| *q)
+    };
+
+    // sending the body as is only wins over codings when the client asked for it
+    let identity = weight("identity").unwrap_or(0.0);
+    let mut best: Option<(&str, f32)> = None;
+    for name in available {
+        let q = weight(name).unwrap_or(0.0);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((name, q));
+        }
+    }
+
+    match best {
+        Some((name, q)) if q >= identity => Some(name),
+        _ => None,
+    }
+}
+
+impl Default for Encodings {
+    fn default() -> Self {
+        #[allow(unused_mut)]
+        let mut encodings = Self::new();
+        #[cfg(feature = "brotli")]
+        encodings.add("br", Brotli);
+        #[cfg(feature = "zstd")]
+        encodings.add("zstd", Zstd);
+        #[cfg(feature = "gzip")]
+        encodings.add("gzip", Gzip);
+        #[cfg(feature = "deflate")]
+        encodings.add("deflate", Deflate);
+        encodings
+    }
+}
+
+impl fmt::Debug for Encodings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.encoders.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+/// Decides which responses are worth compressing.
+///
+/// Only bodies held in memory are considered. Tiny bodies grow rather than shrink once
+/// compressed and already compressed formats (images, archives, ...) do not shrink
+/// at all, so by default only textual types of at least `min_size` bytes are.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    /// Smallest body compressed, in bytes
+    pub min_size: usize,
+    /// Media types compressed: exact (`application/json`) or per top-level type
+    /// (`text/*`). Types with a `+json` or `+xml` suffix are always included.
+    pub mime_types: Vec<String>,
+    pub level: CompressionLevel,
+    /// Sends the body as is when compressing did not make it smaller
+    pub only_if_smaller: bool,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            min_size: 256,
+            mime_types: [
+                "text/*",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "application/wasm",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            level: CompressionLevel::Default,
+            only_if_smaller: true,
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Whether `response` should be compressed, whatever the client accepts.
+    pub fn should_compress(&self, response: &HttpResponse) -> bool {
+        // no content (1xx, 204, 304) or a byte range of the uncompressed body (206)
+        if matches!(response.status_code, 100..=199 | 204 | 206 | 304) {
+            return false;
+        }
+        if response.headers.contains("Content-Encoding")
+            || response.headers.has_token("Cache-Control", "no-transform")
+        {
+            return false;
+        }
+        match response.body.as_bytes() {
+            Some(body) if body.len() >= self.min_size => {}
+            _ => return false,
+        }
+
+        let Some(content_type) = response.headers.get("Content-Type") else {
+            return false;
+        };
+        self.allows_mime_type(content_type)
+    }
+
+    fn allows_mime_type(&self, content_type: &str) -> bool {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        if essence.ends_with("+json") || essence.ends_with("+xml") {
+            return true;
+        }
+        let top_level = essence.split('/').next().unwrap_or("");
+        self.mime_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(allowed_top) => allowed_top.eq_ignore_ascii_case(top_level),
+                None => allowed.eq_ignore_ascii_case(&essence),
+            })
+    }
+}
+
+/// Parses `coding[;q=weight]`, ignoring entries with an invalid weight.
+fn parse_coding(item: &str) -> Option<(String, f32)> {
+    let (coding, q) = parse_weighted(item)?;
+    // x-gzip is an alias kept for old clients
+    let coding = if coding == "x-gzip" {
+        "gzip".to_string()
+    } else {
+        coding
+    };
+    Some((coding, q))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AVAILABLE: [&str; 3] = ["br", "zstd", "gzip"];
+
+    fn negotiate(accept_encoding: &str) -> Option<&'static str> {
+        negotiate_coding(std::iter::once(accept_encoding), AVAILABLE)
+    }
+
+    #[test]
+    fn negotiate_coding_follows_weights() {
+        let cases: &[(&str, Option<&str>)] = &[
+            ("", None),
+            ("gzip", Some("gzip")),
+            ("GZIP", Some("gzip")),
+            ("x-gzip", Some("gzip")),
+            ("deflate", None),
+            // a zero weight refuses the coding
+            ("gzip;q=0", None),
+            ("gzip;q=0, zstd", Some("zstd")),
+            ("gzip;q=0.5, zstd;q=0.8", Some("zstd")),
+            // ties go to the server's order
+            ("gzip, zstd, br", Some("br")),
+            ("gzip;q=0.5, zstd;q=0.5", Some("zstd")),
+            ("*", Some("br")),
+            ("*;q=0", None),
+            ("*;q=0, gzip", Some("gzip")),
+            ("br;q=0, *", Some("zstd")),
+            // identity only wins over codings it outweighs or ties
+            ("identity, gzip;q=0.5", None),
+            ("identity;q=0.5, gzip", Some("gzip")),
+            ("identity;q=0.5, gzip;q=0.5", Some("gzip")),
+            // entries with an invalid weight are skipped
+            ("gzip;q=2, zstd;q=0.1", Some("zstd")),
+            ("gzip;q=-1", None),
+            ("gzip;q=NaN", None),
+            ("gzip;q=abc", None),
+            ("br;q=nan, gzip;q=0.1", Some("gzip")),
+        ];
+        for (accept_encoding, expected) in cases {
+            assert_eq!(negotiate(accept_encoding), *expected, "{accept_encoding:?}");
+        }
+    }
+
+    #[test]
+    fn negotiate_coding_joins_header_lines() {
+        let lines = ["gzip;q=0.2", "zstd;q=0.9"];
+        assert_eq!(negotiate_coding(lines.into_iter(), AVAILABLE), Some("zstd"));
+    }
+
+    #[test]
+    fn parse_coding_reads_weight() {
+        let cases: &[(&str, Option<(&str, f32)>)] = &[
+            ("gzip", Some(("gzip", 1.0))),
+            (" Br ; q=0.3 ", Some(("br", 0.3))),
+            ("x-gzip;Q=1", Some(("gzip", 1.0))),
+            ("zstd;level=3;q=0", Some(("zstd", 0.0))),
+            ("", None),
+            (";q=1", None),
+            ("gzip;q=1.5", None),
+            ("gzip;q=NaN", None),
+            ("gzip;q=", None),
+        ];
+        for (item, expected) in cases {
+            let parsed = parse_coding(item);
+            let parsed = parsed.as_ref().map(|(coding, q)| (coding.as_str(), *q));
+            assert_eq!(parsed, *expected, "{item:?}");
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use crate::core::server::Context;
+use crate::types::request::HttpRequest;
+use crate::types::response::HttpResponse;
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+pub type SharedHandler = Arc<dyn Handler>;
+
+/// Something that can answer a request routed to it.
+///
+/// It is implemented for every `async fn(HttpRequest, Context) -> HttpResponse` and
+/// for closures returning such a future, so most applications never implement it by
+/// hand. Implementing it directly is useful for handlers which carry their own
+/// configuration.
+pub trait Handler: Send + Sync + 'static {
+    fn call(&self, req: HttpRequest, ctx: Context) -> BoxFuture<HttpResponse>;
+}
+
+impl<F, Fut> Handler for F
+where
+    F: Fn(HttpRequest, Context) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HttpResponse> + Send + 'static,
+{
+    fn call(&self, req: HttpRequest, ctx: Context) -> BoxFuture<HttpResponse> {
+        Box::pin(self(req, ctx))
+    }
+}
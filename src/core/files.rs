@@ -0,0 +1,275 @@
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+use thiserror::Error;
+use tokio::fs;
+
+use crate::types::status::StatusCode;
+
+#[derive(Debug, Error)]
+pub enum ResolveError {
+    #[error("path escapes the served directory")]
+    Forbidden,
+    #[error("file not found")]
+    NotFound,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl ResolveError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ResolveError::Forbidden => StatusCode::Forbidden,
+            ResolveError::NotFound => StatusCode::NotFound,
+            ResolveError::Io(_) => StatusCode::InternalServerError,
+        }
+    }
+}
+
+/// File name extensions of the precompressed variants of a file, by content coding,
+/// in order of preference.
+const PRECOMPRESSED: [(&str, &str); 3] = [("br", ".br"), ("zstd", ".zst"), ("gzip", ".gz")];
+
+/// Maps user supplied relative paths to files inside a served directory.
+///
+/// Paths are resolved against the canonical root and canonicalized themselves, so
+/// neither `..` segments nor symlinks pointing outside of the root can be used to
+/// reach other files: those are reported as [`ResolveError::Forbidden`].
+#[derive(Debug, Clone)]
+pub struct FileResolver {
+    root: PathBuf,
+}
+
+impl FileResolver {
+    pub async fn new(root: impl AsRef<Path>) -> Result<Self, ResolveError> {
+        let root = fs::canonicalize(root).await.map_err(not_found_or_io)?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves `relative` to an existing file or directory inside the root.
+    pub async fn resolve(&self, relative: &str) -> Result<PathBuf, ResolveError> {
+        let candidate = self.root.join(sanitize(relative)?);
+        let canonical = fs::canonicalize(&candidate)
+            .await
+            .map_err(not_found_or_io)?;
+        self.check_inside(canonical)
+    }
+
+    /// Resolves `relative` to a path inside the root that can be written to. The file
+    /// itself may not exist yet, but its parent directory must.
+    pub async fn resolve_new(&self, relative: &str) -> Result<PathBuf, ResolveError> {
+        let relative = sanitize(relative)?;
+        let Some(name) = relative.file_name() else {
+            return Err(ResolveError::Forbidden);
+        };
+
+        let candidate = self.root.join(&relative);
+        let parent = candidate.parent().unwrap_or(&self.root);
+        let parent = self.check_inside(fs::canonicalize(parent).await.map_err(not_found_or_io)?)?;
+
+        match fs::symlink_metadata(&candidate).await {
+            // writing through a symlink follows it, wherever it points
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::canonicalize(&candidate)
+                    .await
+                    .map_err(|_| ResolveError::Forbidden)?;
+                self.check_inside(target)
+            }
+            Ok(_) => Ok(parent.join(name)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(parent.join(name)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Lists the precompressed variants of `relative` found next to it, e.g.
+    /// `app.js.br` or `app.js.gz`, with their content coding.
+    pub async fn precompressed(&self, relative: &str) -> Vec<(&'static str, PathBuf)> {
+        let mut variants = Vec::new();
+        for (coding, extension) in PRECOMPRESSED {
+            if let Ok(path) = self.resolve(&format!("{}{}", relative, extension)).await
+                && fs::metadata(&path)
+                    .await
+                    .is_ok_and(|metadata| metadata.is_file())
+            {
+                variants.push((coding, path));
+            }
+        }
+        variants
+    }
+
+    fn check_inside(&self, path: PathBuf) -> Result<PathBuf, ResolveError> {
+        if path.starts_with(&self.root) {
+            Ok(path)
+        } else {
+            Err(ResolveError::Forbidden)
+        }
+    }
+}
+
+/// Keeps only the normal components of `relative`, rejecting anything that would
+/// climb out of, or replace, the root.
+fn sanitize(relative: &str) -> Result<PathBuf, ResolveError> {
+    if relative.contains('\0') {
+        return Err(ResolveError::Forbidden);
+    }
+
+    let mut path = PathBuf::new();
+    for component in Path::new(relative.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(ResolveError::Forbidden);
+            }
+        }
+    }
+    Ok(path)
+}
+
+fn not_found_or_io(err: std::io::Error) -> ResolveError {
+    if err.kind() == ErrorKind::NotFound {
+        ResolveError::NotFound
+    } else {
+        ResolveError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::url::percent_decode;
+
+    /// A served directory holding `inside.txt`, next to a directory holding
+    /// `secret.txt` which must stay out of reach.
+    async fn fixture(test: &str) -> (PathBuf, FileResolver) {
+        let base = std::env::temp_dir().join(format!("files-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("root")).unwrap();
+        std::fs::create_dir_all(base.join("outside")).unwrap();
+        std::fs::write(base.join("root/inside.txt"), "inside").unwrap();
+        std::fs::write(base.join("outside/secret.txt"), "secret").unwrap();
+        let resolver = FileResolver::new(base.join("root")).await.unwrap();
+        (base, resolver)
+    }
+
+    #[tokio::test]
+    async fn refuses_parent_segments() {
+        let (base, resolver) = fixture("parent").await;
+        for relative in [
+            "../outside/secret.txt",
+            "inside/../../outside/secret.txt",
+            &percent_decode("%2e%2e/outside/secret.txt", false),
+            &percent_decode("%2E%2E%2Foutside%2Fsecret.txt", false),
+        ] {
+            assert!(
+                matches!(
+                    resolver.resolve(relative).await,
+                    Err(ResolveError::Forbidden)
+                ),
+                "{relative}"
+            );
+            assert!(
+                matches!(
+                    resolver.resolve_new(relative).await,
+                    Err(ResolveError::Forbidden)
+                ),
+                "{relative}"
+            );
+        }
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_absolute_paths_inside_the_root() {
+        let (base, resolver) = fixture("absolute").await;
+        let inside = resolver.root().join("inside.txt");
+        assert_eq!(resolver.resolve("/inside.txt").await.unwrap(), inside);
+        assert_eq!(resolver.resolve("//inside.txt").await.unwrap(), inside);
+
+        let secret = base.join("outside/secret.txt");
+        assert!(matches!(
+            resolver.resolve(secret.to_str().unwrap()).await,
+            Err(ResolveError::NotFound)
+        ));
+        assert_eq!(
+            resolver.resolve_new("/new.txt").await.unwrap(),
+            resolver.root().join("new.txt")
+        );
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_nul_bytes() {
+        let (base, resolver) = fixture("nul").await;
+        assert!(matches!(
+            resolver.resolve("inside.txt\0.png").await,
+            Err(ResolveError::Forbidden)
+        ));
+        assert!(matches!(
+            resolver.resolve_new("new\0.txt").await,
+            Err(ResolveError::Forbidden)
+        ));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuses_symlinks_leaving_the_root() {
+        let (base, resolver) = fixture("symlink").await;
+        let root = resolver.root().to_path_buf();
+        std::os::unix::fs::symlink(base.join("outside/secret.txt"), root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(base.join("outside"), root.join("dir")).unwrap();
+        std::os::unix::fs::symlink(root.join("inside.txt"), root.join("alias.txt")).unwrap();
+
+        for relative in ["link.txt", "dir/secret.txt"] {
+            assert!(
+                matches!(
+                    resolver.resolve(relative).await,
+                    Err(ResolveError::Forbidden)
+                ),
+                "{relative}"
+            );
+        }
+        // writing through the link would replace the file it points to
+        assert!(matches!(
+            resolver.resolve_new("link.txt").await,
+            Err(ResolveError::Forbidden)
+        ));
+        assert!(matches!(
+            resolver.resolve_new("dir/new.txt").await,
+            Err(ResolveError::Forbidden)
+        ));
+
+        // links staying inside the root are fine
+        let inside = root.join("inside.txt");
+        assert_eq!(resolver.resolve("alias.txt").await.unwrap(), inside);
+        assert_eq!(resolver.resolve_new("alias.txt").await.unwrap(), inside);
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn handles_dangling_symlinks() {
+        let (base, resolver) = fixture("dangling").await;
+        let root = resolver.root().to_path_buf();
+        std::os::unix::fs::symlink(base.join("outside/missing.txt"), root.join("dangling"))
+            .unwrap();
+
+        assert!(matches!(
+            resolver.resolve("dangling").await,
+            Err(ResolveError::NotFound)
+        ));
+        // creating the target would write outside of the root
+        assert!(matches!(
+            resolver.resolve_new("dangling").await,
+            Err(ResolveError::Forbidden)
+        ));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
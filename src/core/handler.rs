@@ -1,53 +1,81 @@
-use flate2::Compression;
-use flate2::write::GzEncoder;
+use bytes::BytesMut;
 
+use super::encoding::{CompressionPolicy, Encoder, Encodings};
+use super::endpoint::{BoxFuture, Handler, SharedHandler};
+use super::listener::Connection;
 use super::logging::Logging;
-use super::parser::Parser;
+use super::middleware::{Next, SharedMiddleware};
+use super::parser::{Parser, RequestLimits, Timeouts};
 use super::router::HttpRouter;
+use super::shutdown::ShutdownSignal;
+use super::upgrade::Upgraded;
 use crate::core::server::Context;
+use crate::types::body::Body;
+use crate::types::headers::HttpHeaders;
+use crate::types::method::HttpRequestMethod;
 use crate::types::request::HttpRequest;
 use crate::types::response::HttpResponse;
+use crate::types::status::StatusCode;
 
-use std::collections::HashSet;
-use std::io::{BufReader, copy};
-use tokio::net::TcpStream;
-use tokio::io::AsyncWriteExt;
-use std::{io::Error, sync::Arc};
+use futures_util::StreamExt;
+use std::{
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const CHUNK_SIZE: usize = 16 * 1024;
 
 pub struct HttpRequestHandler {
     logging_enabled: bool,
     router: Arc<HttpRouter>,
-    enconding_schemes: HashSet<String>,
+    middlewares: Arc<[SharedMiddleware]>,
+    encodings: Arc<Encodings>,
+    compression: CompressionPolicy,
+    limits: RequestLimits,
+    timeouts: Timeouts,
+    shutdown: Option<ShutdownSignal>,
 }
 
 impl HttpRequestHandler {
-    pub fn new(router: Arc<HttpRouter>) -> Self {
+    pub fn new(router: Arc<HttpRouter>, middlewares: Arc<[SharedMiddleware]>) -> Self {
         Self {
             logging_enabled: false,
-            router: router,
-            enconding_schemes: HashSet::from([String::from("gzip")]),
+            router,
+            middlewares,
+            encodings: Arc::new(Encodings::default()),
+            compression: CompressionPolicy::default(),
+            limits: RequestLimits::default(),
+            timeouts: Timeouts::default(),
+            shutdown: None,
         }
     }
 
-    pub async fn handle_incoming_request(
+    pub async fn handle_incoming_request<S: Connection>(
         &self,
-        mut socket: TcpStream,
+        mut socket: S,
         ctx: &Context,
     ) -> Result<(), Error> {
-        let parser = Parser::new();
+        let mut parser = Parser::with_limits(self.limits.clone());
+        parser.set_timeouts(self.timeouts.clone());
+        if let Some(shutdown) = &self.shutdown {
+            parser.set_shutdown(shutdown.clone());
+        }
 
         loop {
             let start = std::time::Instant::now();
-            let parse_result = parser.parse_http_request(&mut socket, self.router.clone()).await;
+            let parse_result = parser.parse_http_request(&mut socket).await;
 
             let request = match parse_result {
                 Ok(request) => request,
-                Err(_) => {
+                Err(err) if err.is_closed() => return Ok(()),
+                Err(err) => {
                     // println!("{}", err);
                     let response = HttpResponse::builder()
-                        .status_code(crate::types::status::StatusCode::BadRequest)
+                        .status_code(err.status_code())
                         .build();
-                    self.write_response(&mut socket, None, &response, true).await?;
+                    self.write_response(&mut socket, None, response, true)
+                        .await?;
                     // println!("-- Bad Request");
                     return Ok(());
                 }
@@ -69,56 +97,74 @@ impl HttpRequestHandler {
                 // println!("{router:?}");
             }
 
-            let r = request.clone();
-            let response = match router.get_handler(&r) {
-                Some(handler) => {
-                    let encoding_schemes = if let Some(scheme) = r.headers.get("Accept-Encoding") {
-                        scheme.split(",").collect()
-                    } else {
-                        Vec::new()
-                    };
+            let mut r = request.clone();
+            let mut response = match router.get_handler(&request) {
+                Some(matched) => {
+                    r.path_params = matched.path_params;
 
-                    let schemes: Vec<String> = encoding_schemes
-                        .iter()
-                        .map(|m| m.trim().to_string())
-                        .filter(|scheme| self.enconding_schemes.contains(&scheme.to_lowercase()))
-                        .collect();
-
-                    let mut res = handler(r, ctx);
-
-                    if !schemes.is_empty() {
-                        let scheme = schemes.get(0).unwrap();
-                        res.headers
-                            .insert("Content-Encoding".to_string(), scheme.to_owned());
-                        match scheme.as_str() {
-                            "gzip" => {
-                                let mut encoder =
-                                    GzEncoder::new(Vec::new(), Compression::default());
-                                let mut reader = BufReader::new(res.body.as_bytes());
-                                copy(&mut reader, &mut encoder).unwrap();
-                                let encoded_body = encoder.finish().unwrap_or_default();
-                                res.encoded = encoded_body;
-                                res.body.clear();
-                            }
-                            _ => {}
-                        }
-                    }
+                    let mut middlewares = self.middlewares.to_vec();
+                    middlewares.extend_from_slice(matched.route.get_middlewares());
+                    let mut res = Next::new(middlewares, matched.handler.clone())
+                        .run(r, ctx.clone())
+                        .await;
 
+                    if matched.route.compression_enabled() {
+                        self.encode_response(&request, &mut res);
+                    }
                     res
                 }
-                _ => HttpResponse::builder()
-                    .status_code(crate::types::status::StatusCode::NotFound)
-                    .build(),
+                _ => {
+                    let mut middlewares = self.middlewares.to_vec();
+                    let endpoint: SharedHandler = match router.find_route(&request.path) {
+                        // the path exists, only the method is missing
+                        Some((route, path_params)) => {
+                            r.path_params = path_params;
+                            middlewares.extend_from_slice(route.get_middlewares());
+                            let allow: Vec<&str> = route
+                                .allowed_methods()
+                                .iter()
+                                .map(|method| method.as_str())
+                                .collect();
+                            let status = if request.method == HttpRequestMethod::OPTIONS {
+                                StatusCode::NoContent
+                            } else {
+                                StatusCode::MethodNotAllowed
+                            };
+                            Arc::new(AllowHandler {
+                                allow: allow.join(", "),
+                                status,
+                            })
+                        }
+                        None => Arc::new(not_found),
+                    };
+                    Next::new(middlewares, endpoint).run(r, ctx.clone()).await
+                }
             };
 
-            let r = request.clone();
-            if let Some(close) = r.headers.get("Connection") {
-                if close.eq_ignore_ascii_case("close") {
-                    self.write_response(&mut socket, Some(&r), &response, true).await?;
-                    break;
-                }
-            } else {
-                self.write_response(&mut socket, Some(&r), &response, false).await?;
+            // after switching protocols, the connection no longer speaks HTTP
+            if response.status_code == StatusCode::SwitchingProtocols as u32
+                && let Some(on_upgrade) = response.upgrade.take()
+            {
+                self.write_response(&mut socket, Some(&request), response, false)
+                    .await?;
+                let upgraded = Upgraded {
+                    io: Box::new(socket),
+                    read_buf: parser.take_buffer(),
+                    shutdown: self.shutdown.clone(),
+                    idle_timeout: self.timeouts.idle,
+                };
+                on_upgrade.run(upgraded).await;
+                return Ok(());
+            }
+
+            // while shutting down, connections are closed after their current request
+            let close = !keep_alive(&request)
+                || self.shutdown.as_ref().is_some_and(|s| s.is_triggered())
+                || response_framing(Some(&request), &response) == Framing::CloseDelimited;
+            self.write_response(&mut socket, Some(&request), response, close)
+                .await?;
+            if close {
+                break;
             }
 
             let elapsed = start.elapsed();
@@ -141,67 +187,317 @@ impl HttpRequestHandler {
         Ok(())
     }
 
-    pub async fn write_response(
+    /// Compresses the body with the coding the client prefers among the registered
+    /// ones, when the compression policy deems it worth it.
+    fn encode_response(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        // streamed bodies are sent as they are produced, uncompressed
+        if self.encodings.is_empty()
+            || response.upgrade.is_some()
+            || is_event_stream(response)
+            || !self.compression.should_compress(response)
+        {
+            return;
+        }
+        let Some(body) = response.body.as_bytes() else {
+            return;
+        };
+
+        // the body now depends on Accept-Encoding, caches have to know
+        let headers = &mut response.headers;
+        if !headers.has_token("Vary", "Accept-Encoding") && !headers.has_token("Vary", "*") {
+            headers.append("Vary", "Accept-Encoding");
+        }
+
+        let accept_encoding = request.headers.get_all("Accept-Encoding");
+        let Some((name, encoder)) = self.encodings.negotiate(accept_encoding) else {
+            return;
+        };
+        match encoder.encode(body, self.compression.level) {
+            Ok(encoded) if !self.compression.only_if_smaller || encoded.len() < body.len() => {
+                headers.insert("Content-Encoding", name);
+                response.body = Body::from(encoded);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                // sent as is instead
+                if self.logging_enabled() {
+                    println!("Cannot encode response with {}: {}", name, err);
+                }
+            }
+        }
+    }
+
+    pub async fn write_response<S: AsyncWrite + Unpin>(
         &self,
-        socket: &mut TcpStream,
+        socket: &mut S,
         request: Option<&HttpRequest>,
-        response: &HttpResponse,
+        response: HttpResponse,
         close_socket: bool,
     ) -> Result<(), Error> {
-        let http_response = self.get_response_str(request, response);
+        let framing = response_framing(request, &response);
+        // without a length nor chunks, the end of the connection marks the end of the body
+        let close_socket = close_socket || framing == Framing::CloseDelimited;
+        let http_response = self.get_response_str(request, &response, framing, close_socket);
 
         if self.logging_enabled() {
             // println!("Response: {:?}", http_response);
         }
 
-        socket.write_all(http_response.as_bytes()).await?;
-        if response.encoded.len() > 0 {
-            socket.write(&response.encoded).await?;
+        self.timed(socket.write_all(http_response.as_bytes()))
+            .await?;
+        // a HEAD response announces the body of the equivalent GET but never sends it
+        if framing != Framing::NoBody && !is_head_request(request) {
+            let chunked = framing == Framing::Chunked;
+            self.write_body(socket, response.body, chunked).await?;
+            if chunked {
+                self.write_last_chunk(socket, &response.trailers).await?;
+            }
         }
-        socket.flush().await?;
+        self.timed(socket.flush()).await?;
 
         if close_socket {
-            socket.shutdown().await?;
+            self.timed(socket.shutdown()).await?;
         }
 
         Ok(())
     }
 
-    fn get_response_str(&self, request: Option<&HttpRequest>, response: &HttpResponse) -> String {
-        let mut http_response = String::from(format!(
+    async fn write_body<S: AsyncWrite + Unpin>(
+        &self,
+        socket: &mut S,
+        body: Body,
+        chunked: bool,
+    ) -> Result<(), Error> {
+        match body {
+            Body::Full(bytes) => self.write_data(socket, &bytes, chunked).await,
+            Body::Reader { reader, length } => {
+                let mut reader = reader.take(length.unwrap_or(u64::MAX));
+                let mut buf = BytesMut::with_capacity(CHUNK_SIZE);
+                let mut written = 0;
+                while reader.read_buf(&mut buf).await? > 0 {
+                    self.write_data(socket, &buf, chunked).await?;
+                    written += buf.len() as u64;
+                    buf.clear();
+                }
+                if !chunked && length.is_some_and(|length| written < length) {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Response body ended before its announced length",
+                    ));
+                }
+                Ok(())
+            }
+            Body::Stream(mut stream) => {
+                // chunks are flushed as they come, e.g. server-sent events must not wait
+                while let Some(chunk) = stream.next().await {
+                    self.write_data(socket, &chunk?, chunked).await?;
+                    self.timed(socket.flush()).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn write_data<S: AsyncWrite + Unpin>(
+        &self,
+        socket: &mut S,
+        data: &[u8],
+        chunked: bool,
+    ) -> Result<(), Error> {
+        if !chunked {
+            // written piecewise so that the write timeout bounds progress, not the whole body
+            for piece in data.chunks(CHUNK_SIZE) {
+                self.timed(socket.write_all(piece)).await?;
+            }
+            return Ok(());
+        }
+        // an empty chunk would mark the end of the body
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.timed(socket.write_all(format!("{:X}\r\n", data.len()).as_bytes()))
+            .await?;
+        self.timed(socket.write_all(data)).await?;
+        self.timed(socket.write_all(b"\r\n")).await
+    }
+
+    async fn write_last_chunk<S: AsyncWrite + Unpin>(
+        &self,
+        socket: &mut S,
+        trailers: &HttpHeaders,
+    ) -> Result<(), Error> {
+        let mut last_chunk = String::from("0\r\n");
+        for (key, value) in trailers.iter() {
+            last_chunk.push_str(format!("{}: {}\r\n", key, value).as_str());
+        }
+        last_chunk.push_str("\r\n");
+        self.timed(socket.write_all(last_chunk.as_bytes())).await
+    }
+
+    /// Runs a write on the connection, failing it if the client stops reading for
+    /// longer than the write timeout.
+    async fn timed<T>(&self, write: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        match self.timeouts.write {
+            Some(limit) => tokio::time::timeout(limit, write)
+                .await
+                .map_err(|_| Error::new(ErrorKind::TimedOut, "Timed out writing the response"))?,
+            None => write.await,
+        }
+    }
+
+    fn get_response_str(
+        &self,
+        request: Option<&HttpRequest>,
+        response: &HttpResponse,
+        framing: Framing,
+        close: bool,
+    ) -> String {
+        let mut http_response = format!(
             "{} {} {}\r\n",
             response.protocol, response.status_code, response.reason
-        ));
-        for (key, value) in &response.headers {
+        );
+        for (key, value) in response.headers.iter() {
+            // framing headers are derived from the body, unless an explicit HEAD handler set them
+            let framing_header = key.eq_ignore_ascii_case("Content-Length")
+                || key.eq_ignore_ascii_case("Transfer-Encoding");
+            if framing_header && framing != Framing::Explicit {
+                continue;
+            }
             http_response.push_str(format!("{}: {}\r\n", key, value).as_str());
         }
 
-        if response.body.len() > 0 {
-            http_response.push_str(format!("Content-Length: {}\r\n", response.body.len()).as_str());
-        } else if response.encoded.len() > 0 {
-            http_response
-                .push_str(format!("Content-Length: {}\r\n", response.encoded.len()).as_str());
-        }
-
-        if let Some(req) = request {
-            if let Some(close) = req.headers.get("Connection") {
-                if close.eq_ignore_ascii_case("close") {
-                    http_response.push_str("Connection: close\r\n");
+        match framing {
+            Framing::Length(length) => {
+                http_response.push_str(format!("Content-Length: {}\r\n", length).as_str())
+            }
+            Framing::Chunked => {
+                http_response.push_str("Transfer-Encoding: chunked\r\n");
+                if !response.trailers.is_empty() {
+                    let names: Vec<&str> = response.trailers.iter().map(|(key, _)| key).collect();
+                    http_response.push_str(format!("Trailer: {}\r\n", names.join(", ")).as_str());
                 }
             }
+            Framing::NoBody | Framing::Explicit | Framing::CloseDelimited => {}
         }
 
-        http_response.push_str("\r\n");
-
-        if response.body.len() > 0 {
-            http_response.push_str(format!("{}", response.body).as_str());
+        if close {
+            http_response.push_str("Connection: close\r\n");
+        } else if is_http_1_0(request) {
+            // HTTP/1.0 clients expect the connection to close unless told otherwise
+            http_response.push_str("Connection: keep-alive\r\n");
         }
 
+        http_response.push_str("\r\n");
+
         http_response
     }
 
-    pub fn add_encoding_scheme(&mut self, scheme: &str) {
-        self.enconding_schemes.insert(scheme.to_string());
+    /// Offers `encoder` to clients accepting the content coding `scheme`.
+    pub fn add_encoding_scheme(&mut self, scheme: &str, encoder: impl Encoder) {
+        Arc::make_mut(&mut self.encodings).add(scheme, encoder);
+    }
+
+    pub fn set_encodings(&mut self, encodings: Arc<Encodings>) {
+        self.encodings = encodings;
+    }
+
+    pub fn set_compression(&mut self, compression: CompressionPolicy) {
+        self.compression = compression;
+    }
+
+    pub fn set_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    pub(crate) fn set_shutdown(&mut self, shutdown: ShutdownSignal) {
+        self.shutdown = Some(shutdown);
+    }
+}
+
+/// How the end of a response body is signalled to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// 1xx, 204 and 304 responses cannot carry a body, nor announce one (RFC 9110,
+    /// section 8.6)
+    NoBody,
+    /// An explicit HEAD handler announced the length itself
+    Explicit,
+    Length(u64),
+    Chunked,
+    /// HTTP/1.0 clients do not understand chunks, the connection is closed instead
+    CloseDelimited,
+}
+
+fn response_framing(request: Option<&HttpRequest>, response: &HttpResponse) -> Framing {
+    if response.status_code < 200 || matches!(response.status_code, 204 | 304) {
+        return Framing::NoBody;
+    }
+    if is_head_request(request)
+        && response.body.is_empty()
+        && response.headers.contains("Content-Length")
+    {
+        return Framing::Explicit;
+    }
+
+    let http_1_0 = is_http_1_0(request);
+    // trailers can only travel after the last chunk
+    let wants_chunks = response.body.len().is_none() || !response.trailers.is_empty();
+    match response.body.len() {
+        _ if wants_chunks && !http_1_0 => Framing::Chunked,
+        Some(length) => Framing::Length(length),
+        None => Framing::CloseDelimited,
+    }
+}
+
+/// HTTP/1.1 connections persist unless closed explicitly, HTTP/1.0 ones only when asked to.
+fn keep_alive(request: &HttpRequest) -> bool {
+    if request.version == "HTTP/1.0" {
+        request.headers.has_token("Connection", "keep-alive")
+    } else {
+        !request.headers.has_token("Connection", "close")
+    }
+}
+
+fn is_http_1_0(request: Option<&HttpRequest>) -> bool {
+    request.is_some_and(|req| req.version == "HTTP/1.0")
+}
+
+fn is_head_request(request: Option<&HttpRequest>) -> bool {
+    request.is_some_and(|req| req.method == HttpRequestMethod::HEAD)
+}
+
+/// Server-sent events must reach the client one by one, compressing them would buffer them.
+fn is_event_stream(response: &HttpResponse) -> bool {
+    response
+        .headers
+        .get("Content-Type")
+        .is_some_and(|value| value.trim().starts_with("text/event-stream"))
+}
+
+async fn not_found(_req: HttpRequest, _ctx: Context) -> HttpResponse {
+    HttpResponse::builder()
+        .status_code(StatusCode::NotFound)
+        .build()
+}
+
+/// Answers requests for a known path with a method it has no handler for: `OPTIONS`
+/// gets the list of allowed methods, anything else a `405 Method Not Allowed`.
+struct AllowHandler {
+    allow: String,
+    status: StatusCode,
+}
+
+impl Handler for AllowHandler {
+    fn call(&self, _req: HttpRequest, _ctx: Context) -> BoxFuture<HttpResponse> {
+        let response = HttpResponse::builder()
+            .status_code(self.status)
+            .header("Allow", &self.allow)
+            .build();
+        Box::pin(async move { response })
     }
 }
 
@@ -217,3 +513,142 @@ impl Logging for HttpRequestHandler {
         self.logging_enabled
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+    use futures_util::stream;
+
+    fn handler() -> HttpRequestHandler {
+        HttpRequestHandler::new(Arc::new(HttpRouter::new()), Arc::from([]))
+    }
+
+    async fn request(head: &str) -> HttpRequest {
+        let raw = format!("{head}\r\n\r\n");
+        Parser::new()
+            .parse_http_request(&mut raw.as_bytes())
+            .await
+            .unwrap()
+    }
+
+    /// Writes `response` to `request` as the handler would, returning the bytes sent.
+    async fn written(request: &HttpRequest, response: HttpResponse, close: bool) -> String {
+        let mut out = Vec::new();
+        handler()
+            .write_response(&mut out, Some(request), response, close)
+            .await
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn ok(body: impl Into<Body>) -> HttpResponse {
+        HttpResponse::builder()
+            .status_code(StatusCode::Ok)
+            .body(body)
+            .build()
+    }
+
+    fn streamed(chunks: &[&'static str]) -> Body {
+        let chunks: Vec<std::io::Result<Bytes>> = chunks
+            .iter()
+            .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())))
+            .collect();
+        Body::from_stream(stream::iter(chunks))
+    }
+
+    #[tokio::test]
+    async fn frames_known_bodies_with_content_length() {
+        let req = request("GET / HTTP/1.1").await;
+        assert_eq!(
+            written(&req, ok("hello"), false).await,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+        );
+    }
+
+    #[tokio::test]
+    async fn chunks_streamed_bodies() {
+        let req = request("GET / HTTP/1.1").await;
+        assert_eq!(
+            written(&req, ok(streamed(&["ab", "", "cde"])), false).await,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n3\r\ncde\r\n0\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_trailers_after_the_last_chunk() {
+        let req = request("GET / HTTP/1.1").await;
+        let response = HttpResponse::builder()
+            .status_code(StatusCode::Ok)
+            .body("abc")
+            .trailer("Checksum", "xyz")
+            .build();
+        assert_eq!(
+            written(&req, response, false).await,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: Checksum\r\n\r\n\
+             3\r\nabc\r\n0\r\nChecksum: xyz\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn closes_to_end_streamed_bodies_for_http_1_0() {
+        let req = request("GET / HTTP/1.0\r\nConnection: keep-alive").await;
+        assert_eq!(
+            written(&req, ok(streamed(&["ab", "cd"])), false).await,
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nabcd"
+        );
+    }
+
+    #[tokio::test]
+    async fn confirms_http_1_0_keep_alive() {
+        let req = request("GET / HTTP/1.0\r\nConnection: keep-alive").await;
+        assert!(keep_alive(&req));
+        assert_eq!(
+            written(&req, ok("hi"), false).await,
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: keep-alive\r\n\r\nhi"
+        );
+
+        let req = request("GET / HTTP/1.0").await;
+        assert!(!keep_alive(&req));
+        assert_eq!(
+            written(&req, ok("hi"), true).await,
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi"
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_no_body_nor_length_for_204_and_304() {
+        let req = request("GET / HTTP/1.1").await;
+        for status in [StatusCode::NoContent, StatusCode::NotModified] {
+            let response = HttpResponse::builder()
+                .status_code(status)
+                .body("ignored")
+                .build();
+            let out = written(&req, response, false).await;
+            assert_eq!(
+                out,
+                format!("HTTP/1.1 {} {}\r\n\r\n", status as u32, status.reason())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn announces_but_does_not_send_head_bodies() {
+        let req = request("HEAD / HTTP/1.1").await;
+        assert_eq!(
+            written(&req, ok("hello"), false).await,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"
+        );
+
+        // an explicit HEAD handler announcing the length itself
+        let response = HttpResponse::builder()
+            .status_code(StatusCode::Ok)
+            .header("Content-Length", "42")
+            .build();
+        assert_eq!(
+            written(&req, response, false).await,
+            "HTTP/1.1 200 OK\r\nContent-Length: 42\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::{fmt, io, net::SocketAddr};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+
+use crate::core::endpoint::BoxFuture;
+
+/// A byte stream a request can be served over: a TCP connection, a Unix socket...
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T> Connection for T where T: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+pub type BoxConnection = Box<dyn Connection>;
+
+/// A connection that was accepted but may still need setting up, e.g. a TLS
+/// handshake. It runs in the connection task so that it never holds up accepting.
+pub type PendingConnection = BoxFuture<io::Result<BoxConnection>>;
+
+/// A socket the server accepts connections on.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(unix::UnixSocketListener),
+    #[cfg(feature = "tls")]
+    Tls(TcpListener, TlsAcceptor),
+}
+
+impl Listener {
+    pub async fn accept(&self) -> io::Result<PendingConnection> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (socket, _) = listener.accept().await?;
+                Ok(ready(socket))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (socket, _) = listener.listener.accept().await?;
+                Ok(ready(socket))
+            }
+            #[cfg(feature = "tls")]
+            Listener::Tls(listener, acceptor) => {
+                let (socket, _) = listener.accept().await?;
+                let acceptor = acceptor.clone();
+                Ok(Box::pin(async move {
+                    let stream = acceptor.accept(socket).await?;
+                    Ok(Box::new(stream) as BoxConnection)
+                }))
+            }
+        }
+    }
+
+    /// The address of a TCP listener.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+            #[cfg(feature = "tls")]
+            Listener::Tls(listener, _) => listener.local_addr().ok(),
+        }
+    }
+}
+
+impl fmt::Debug for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => f.debug_tuple("Tcp").field(listener).finish(),
+            #[cfg(unix)]
+            Listener::Unix(listener) => f.debug_tuple("Unix").field(listener).finish(),
+            #[cfg(feature = "tls")]
+            Listener::Tls(listener, _) => f.debug_tuple("Tls").field(listener).finish(),
+        }
+    }
+}
+
+fn ready(connection: impl Connection) -> PendingConnection {
+    let connection: BoxConnection = Box::new(connection);
+    Box::pin(async move { Ok(connection) })
+}
+
+#[cfg(unix)]
+pub mod unix {
+    use std::{
+        fs, io,
+        os::unix::fs::{FileTypeExt, PermissionsExt},
+        path::{Path, PathBuf},
+    };
+
+    use tokio::net::UnixListener;
+
+    /// A Unix domain socket listener, whose socket file is removed when it is dropped.
+    #[derive(Debug)]
+    pub struct UnixSocketListener {
+        pub(super) listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl UnixSocketListener {
+        /// Binds a socket at `path`, replacing a stale socket file left behind by a
+        /// server which did not shut down cleanly. `mode` sets the socket file
+        /// permissions, e.g. `0o660` to let a reverse proxy in the same group connect.
+        pub fn bind(path: impl AsRef<Path>, mode: Option<u32>) -> io::Result<Self> {
+            let path = path.as_ref().to_path_buf();
+            remove_stale_socket(&path)?;
+
+            let listener = UnixListener::bind(&path)?;
+            if let Some(mode) = mode {
+                fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+            }
+            Ok(Self { listener, path })
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    impl Drop for UnixSocketListener {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Removes the socket file at `path` when nothing listens on it anymore. Regular
+    /// files and sockets still in use are left alone and reported as errors.
+    fn remove_stale_socket(path: &Path) -> io::Result<()> {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another server", path.display()),
+            )),
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+            Err(err) => Err(err),
+        }
+    }
+}
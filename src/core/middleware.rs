@@ -0,0 +1,60 @@
+use std::{future::Future, sync::Arc};
+
+use crate::core::endpoint::{BoxFuture, SharedHandler};
+use crate::core::server::Context;
+use crate::types::request::HttpRequest;
+use crate::types::response::HttpResponse;
+
+pub type SharedMiddleware = Arc<dyn Middleware>;
+
+/// A unit of cross-cutting behaviour wrapped around request dispatch.
+///
+/// Middlewares form an onion: each one receives the request and the [`Next`] layer,
+/// and decides whether to call it, what to change in the request before doing so and
+/// what to change in the response afterwards. Server-wide middlewares run first, in
+/// the order they were added, followed by the ones attached to the matched route.
+///
+/// It is implemented for closures `Fn(HttpRequest, Context, Next) -> impl Future<Output = HttpResponse>`.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, req: HttpRequest, ctx: Context, next: Next) -> BoxFuture<HttpResponse>;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(HttpRequest, Context, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HttpResponse> + Send + 'static,
+{
+    fn handle(&self, req: HttpRequest, ctx: Context, next: Next) -> BoxFuture<HttpResponse> {
+        Box::pin(self(req, ctx, next))
+    }
+}
+
+/// The rest of the middleware chain, ending with the route handler.
+#[derive(Clone)]
+pub struct Next {
+    middlewares: Arc<[SharedMiddleware]>,
+    index: usize,
+    endpoint: SharedHandler,
+}
+
+impl Next {
+    pub fn new(middlewares: Vec<SharedMiddleware>, endpoint: SharedHandler) -> Self {
+        Self {
+            middlewares: middlewares.into(),
+            index: 0,
+            endpoint,
+        }
+    }
+
+    /// Passes the request to the next middleware, or to the handler once every
+    /// middleware has run.
+    pub async fn run(mut self, req: HttpRequest, ctx: Context) -> HttpResponse {
+        match self.middlewares.get(self.index).cloned() {
+            Some(middleware) => {
+                self.index += 1;
+                middleware.handle(req, ctx, self).await
+            }
+            None => self.endpoint.call(req, ctx).await,
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::{collections::HashMap, path::Path};
+
+use tokio::{fs, io::AsyncReadExt};
+
+/// Sent when the type of a file cannot be told.
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// How many leading bytes of a file are looked at when sniffing its type.
+const SNIFF_LEN: usize = 512;
+
+/// Built-in extension to media type mapping, covering the files usually served by a
+/// web server.
+const BUILTIN: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("wasm", "application/wasm"),
+];
+
+/// Leading bytes of binary formats recognized when sniffing.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x00asm", "application/wasm"),
+];
+
+/// Tells the `Content-Type` of served files.
+///
+/// Types are looked up from the file name extension, in a built-in table which can
+/// be extended or overridden with [`MimeTypes::insert`]. When sniffing is enabled,
+/// files with an unknown extension get the type recognized from their first bytes
+/// instead of [`DEFAULT_MIME_TYPE`]. Textual types are sent with a `charset`
+/// parameter, `utf-8` unless changed with [`MimeTypes::set_charset`].
+#[derive(Debug, Clone)]
+pub struct MimeTypes {
+    by_extension: HashMap<String, String>,
+    sniff: bool,
+    charset: Option<String>,
+}
+
+impl MimeTypes {
+    /// An empty table, which tells no type but through sniffing.
+    pub fn new() -> Self {
+        Self {
+            by_extension: HashMap::new(),
+            sniff: false,
+            charset: Some("utf-8".to_string()),
+        }
+    }
+
+    /// Maps the file name `extension` (without the dot) to `mime_type`, replacing
+    /// any type it had.
+    pub fn insert(&mut self, extension: &str, mime_type: &str) {
+        self.by_extension.insert(
+            extension.trim_start_matches('.').to_ascii_lowercase(),
+            mime_type.to_string(),
+        );
+    }
+
+    pub fn remove(&mut self, extension: &str) {
+        self.by_extension
+            .remove(&extension.trim_start_matches('.').to_ascii_lowercase());
+    }
+
+    /// Whether files with an unknown extension are sniffed. Off by default.
+    pub fn set_sniffing(&mut self, sniff: bool) {
+        self.sniff = sniff;
+    }
+
+    /// Sets the charset announced for textual types, `None` to leave it out.
+    pub fn set_charset(&mut self, charset: Option<&str>) {
+        self.charset = charset.map(String::from);
+    }
+
+    /// The type mapped to the extension of `path`, if any.
+    pub fn lookup(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        self.by_extension.get(&extension).map(String::as_str)
+    }
+
+    /// The `Content-Type` to send for the file at `path`, reading its first bytes when
+    /// sniffing is needed.
+    pub async fn content_type(&self, path: &Path) -> String {
+        let mime_type = match self.lookup(path) {
+            Some(mime_type) => mime_type,
+            None if self.sniff => sniff_file(path).await.unwrap_or(DEFAULT_MIME_TYPE),
+            None => DEFAULT_MIME_TYPE,
+        };
+        self.with_charset(mime_type)
+    }
+
+    /// Adds the charset parameter to textual types which do not have one.
+    pub fn with_charset(&self, mime_type: &str) -> String {
+        match &self.charset {
+            Some(charset) if is_textual(mime_type) && !mime_type.contains(';') => {
+                format!("{}; charset={}", mime_type, charset)
+            }
+            _ => mime_type.to_string(),
+        }
+    }
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        let mut mime_types = Self::new();
+        for (extension, mime_type) in BUILTIN {
+            mime_types.insert(extension, mime_type);
+        }
+        mime_types
+    }
+}
+
+/// Recognizes the type of `data`, the first bytes of a file, from well known
+/// signatures of binary formats and markup. Other valid UTF-8 content is taken as
+/// plain text.
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    if let Some((_, mime_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+    {
+        return Some(mime_type);
+    }
+    if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    let start = data
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(data.len());
+    let markup = data[start..].to_ascii_lowercase();
+    if markup.starts_with(b"<!doctype html") || markup.starts_with(b"<html") {
+        return Some("text/html");
+    }
+    if markup.starts_with(b"<svg") {
+        return Some("image/svg+xml");
+    }
+    if markup.starts_with(b"<?xml") {
+        return Some("application/xml");
+    }
+
+    is_text(data).then_some("text/plain")
+}
+
+async fn sniff_file(path: &Path) -> Option<&'static str> {
+    let file = fs::File::open(path).await.ok()?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    file.take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .await
+        .ok()?;
+    sniff(&head)
+}
+
+/// Whether `data` is UTF-8 text without control characters, allowing a character cut
+/// at the end.
+fn is_text(data: &[u8]) -> bool {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&data[..err.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+    !text.is_empty()
+        && !text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'))
+}
+
+fn is_textual(mime_type: &str) -> bool {
+    let essence = mime_type.to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/javascript" | "application/xml"
+        )
+}
@@ -1,5 +1,18 @@
+pub mod encoding;
+pub mod endpoint;
+pub mod files;
 pub mod handler;
+pub mod listener;
 pub mod logging;
+pub mod middleware;
+pub mod mime;
 pub mod parser;
 pub mod router;
 pub mod server;
+pub mod shutdown;
+pub mod static_files;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod upgrade;
+pub mod url;
+pub mod websocket;
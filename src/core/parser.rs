@@ -1,112 +1,243 @@
-use regex::Regex;
-use tokio::io::AsyncReadExt;
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 use crate::core::logging::Logging;
+use crate::core::shutdown::{ShutdownSignal, wait_for_shutdown};
 
-use crate::core::router::HttpRouter;
+use crate::core::url;
+use crate::types::headers::HttpHeaders;
 use crate::types::method::*;
 use crate::types::request::*;
+use crate::types::status::StatusCode;
 
-use tokio::net::TcpStream;
-use std::sync::Arc;
-use std::{collections::HashMap, io::Error};
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::{Instant, timeout_at};
+
+/// Upper bounds on the size of a request, checked while it is being read so that a
+/// client cannot make the server buffer an unbounded amount of data.
+#[derive(Debug, Clone)]
+pub struct RequestLimits {
+    /// Longest accepted request line (method, target and version), answered with 414
+    pub max_request_line: usize,
+    /// Most header (or trailer) fields accepted, answered with 431
+    pub max_headers: usize,
+    /// Most bytes accepted for all header (or trailer) fields together, answered with 431
+    pub max_header_bytes: usize,
+    /// Largest accepted body, after chunked decoding, answered with 413
+    pub max_body_size: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_headers: 100,
+            max_header_bytes: 64 * 1024,
+            max_body_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// How long a connection may take to make progress before it is given up on.
+/// `None` waits forever.
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    /// Time allowed to receive a whole request head once its first byte arrived,
+    /// answered with 408
+    pub header_read: Option<Duration>,
+    /// Time allowed to receive a whole request body, answered with 408
+    pub body_read: Option<Duration>,
+    /// Time a kept-alive connection may wait for its next request, closed silently
+    pub idle: Option<Duration>,
+    /// Time allowed for each write of the response to the client to complete
+    pub write: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            header_read: Some(Duration::from_secs(10)),
+            body_read: Some(Duration::from_secs(60)),
+            idle: Some(Duration::from_secs(75)),
+            write: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error(transparent)]
+    Io(#[from] Error),
+    #[error("malformed request: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported transfer coding")]
+    UnsupportedTransferCoding,
+    #[error("request line is too long")]
+    UriTooLong,
+    #[error("request header fields are too large")]
+    HeadersTooLarge,
+    #[error("request body is too large")]
+    PayloadTooLarge,
+    #[error("timed out reading the request")]
+    Timeout,
+    #[error("connection idle for too long")]
+    Idle,
+}
+
+impl ParseError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ParseError::Io(_) | ParseError::Malformed(_) => StatusCode::BadRequest,
+            ParseError::UnsupportedTransferCoding => StatusCode::NotImplemented,
+            ParseError::UriTooLong => StatusCode::UriTooLong,
+            ParseError::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+            ParseError::PayloadTooLarge => StatusCode::PayloadTooLarge,
+            ParseError::Timeout | ParseError::Idle => StatusCode::RequestTimeout,
+        }
+    }
+
+    /// Whether the connection was closed by the client, or left idle, between two
+    /// requests. No response is due in that case.
+    pub fn is_closed(&self) -> bool {
+        match self {
+            ParseError::Io(err) => err.kind() == ErrorKind::UnexpectedEof,
+            ParseError::Idle => true,
+            _ => false,
+        }
+    }
+}
 
 pub struct Parser {
     logging_enabled: bool,
+    limits: RequestLimits,
+    timeouts: Timeouts,
+    // when the part of the request being read must have arrived
+    deadline: Option<Instant>,
+    shutdown: Option<ShutdownSignal>,
+    // bytes read from the socket that belong to the next (pipelined) request
+    buffer: BytesMut,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Parser {
     pub fn new() -> Self {
+        Self::with_limits(RequestLimits::default())
+    }
+
+    pub fn with_limits(limits: RequestLimits) -> Self {
         Self {
             logging_enabled: false,
+            limits,
+            timeouts: Timeouts::default(),
+            deadline: None,
+            shutdown: None,
+            buffer: BytesMut::with_capacity(1024),
         }
     }
 
-    pub async fn parse_http_request(
-        &self,
-        socket: &mut TcpStream,
-        router: Arc<HttpRouter>,
-    ) -> Result<HttpRequest, Error> {
-        let mut request_content = String::new();
-        let mut buf = [0u8; 1024];
-        loop {
-            match socket.read(&mut buf).await {
-                Ok(size) => {
-                    if size > 0 {
-                        let st = std::str::from_utf8(&buf[0..size]).unwrap();
-                        request_content.push_str(st);
-                        match request_content.find("\r\n\r\n") {
-                            Some(_) => {
-                                break;
-                            }
-                            _ => {}
-                        }
-                    } else {
-                        break;
-                    }
-                }
-                Err(_) => {
-                    if self.logging_enabled {
-                        // println!("There was an error: {err:?}");
-                    }
-                    break;
-                }
-            }
-        }
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
 
-        let req: Vec<&str> = request_content
-            .split("\r\n\r\n")
-            .filter(|x| !x.is_empty())
-            .collect();
+    /// Stops waiting for a next request, as if the connection idled out, once
+    /// `shutdown` is triggered. Requests already being received are still read.
+    pub(crate) fn set_shutdown(&mut self, shutdown: ShutdownSignal) {
+        self.shutdown = Some(shutdown);
+    }
 
-        // println!("parsed-request: {req:?}");
+    /// Takes the bytes received after the last parsed request, when the connection
+    /// stops speaking HTTP.
+    pub(crate) fn take_buffer(&mut self) -> BytesMut {
+        std::mem::take(&mut self.buffer)
+    }
 
-        let req_headers = match req.get(0) {
-            Some(st) => st,
-            None => "",
+    pub async fn parse_http_request<S: AsyncRead + Unpin>(
+        &mut self,
+        socket: &mut S,
+    ) -> Result<HttpRequest, ParseError> {
+        let mut head_started = false;
+        let head_end = loop {
+            if let Some(pos) = find(&self.buffer, b"\r\n\r\n") {
+                break pos;
+            }
+            self.check_head_size(&self.buffer)?;
+            if self.buffer.is_empty() {
+                // nothing of the next request yet, the connection is idle
+                self.deadline = deadline_after(self.timeouts.idle);
+                let mut shutdown = self.shutdown.clone();
+                let read = tokio::select! {
+                    read = self.fill_buffer(socket) => read,
+                    _ = wait_for_shutdown(&mut shutdown) => Err(ParseError::Idle),
+                };
+                read.map_err(|err| match err {
+                    ParseError::Timeout => ParseError::Idle,
+                    err => err,
+                })?;
+                continue;
+            }
+            if !head_started {
+                head_started = true;
+                self.deadline = deadline_after(self.timeouts.header_read);
+            }
+            self.fill_buffer(socket).await?;
         };
 
+        let head = self.buffer.split_to(head_end + 4);
+        self.check_head_size(&head[..head_end + 2])?;
+        let req_headers = std::str::from_utf8(&head[..head_end])
+            .map_err(|_| ParseError::Malformed("request head is not valid UTF-8"))?;
+
+        // println!("parsed-request: {req_headers:?}");
+
         let mut lines = req_headers.lines();
-        let request_line = match lines.next() {
-            Some(first_line) => Some(self.parse_request_line(first_line)),
-            _ => None,
-        };
+        let request_line = lines
+            .next()
+            .map(|first_line| self.parse_request_line(first_line))
+            .transpose()?;
 
         match request_line {
             Some(request_line) => {
-                if request_line.0 == HttpRequestMethod::UNKNOWN {
-                    return Err(Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Invalid request method",
-                    ));
+                if request_line.method == HttpRequestMethod::UNKNOWN {
+                    return Err(ParseError::Malformed("invalid request method"));
                 }
 
                 // println!("parsed-headers: {req_headers:?}");
 
-                let headers = self.parse_headers(lines);
-                let body = self.parse_request_body(socket, req, &headers).await;
-                let path_params = self.parse_path_params(&request_line.1, router.as_ref());
-                // dbg!(&path_params);
+                let headers = self.parse_headers(lines)?;
+                let (body, trailers) = self.parse_request_body(socket, &headers).await?;
 
-                Ok(HttpRequest::new(
-                    request_line.0,
-                    request_line.1,
-                    request_line.2,
-                    body,
-                    path_params,
-                    headers,
-                ))
+                Ok(HttpRequest::new(request_line, body, headers, trailers))
             }
-            _ => Err(Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid request",
-            )),
+            _ => Err(ParseError::Malformed("invalid request")),
+        }
+    }
+
+    /// Checks a request head, complete or still being received, against the limits.
+    fn check_head_size(&self, head: &[u8]) -> Result<(), ParseError> {
+        let line_length = find(head, b"\r\n").unwrap_or(head.len());
+        if line_length > self.limits.max_request_line {
+            return Err(ParseError::UriTooLong);
+        }
+        if head.len() - line_length > self.limits.max_header_bytes + 4 {
+            return Err(ParseError::HeadersTooLarge);
         }
+        Ok(())
     }
 
-    fn parse_request_line(&self, line: &str) -> RequestLine {
-        let collect: Vec<&str> = line.split(' ').collect();
-        let method = match *collect.get(0).unwrap() {
+    /// Parses `method SP request-target SP HTTP-version` (RFC 9112, section 3).
+    fn parse_request_line(&self, line: &str) -> Result<RequestLine, ParseError> {
+        let parts: Vec<&str> = line.split(' ').collect();
+        let [method, target, version] = parts[..] else {
+            return Err(ParseError::Malformed("invalid request line"));
+        };
+        let method = match method {
             "GET" => HttpRequestMethod::GET,
             "POST" => HttpRequestMethod::POST,
             "PUT" => HttpRequestMethod::PUT,
@@ -118,96 +249,276 @@ impl Parser {
             "TRACE" => HttpRequestMethod::TRACE,
             _ => HttpRequestMethod::UNKNOWN,
         };
-
-        let mut target = String::from(*collect.get(1).unwrap_or(&""));
-        while target.len() > 1 && target.ends_with("/") {
-            target.pop();
+        if !is_http1_version(version) {
+            return Err(ParseError::Malformed("invalid HTTP version"));
         }
 
-        let version = String::from(*collect.get(2).unwrap_or(&""));
+        let target = target.to_string();
+        let (raw_path, raw_query) = target.split_once('?').unwrap_or((&target, ""));
+        // origin-form, asterisk-form, or absolute-form whose path is routed
+        let raw_path = if raw_path.starts_with('/') || raw_path == "*" {
+            raw_path
+        } else {
+            match absolute_form_path(raw_path) {
+                Some(path) => path,
+                None => return Err(ParseError::Malformed("invalid request target")),
+            }
+        };
 
-        RequestLine(method, target, version)
+        let mut path = url::percent_decode(raw_path, false);
+        while path.len() > 1 && path.ends_with("/") {
+            path.pop();
+        }
+        let query = url::parse_query(raw_query);
+
+        Ok(RequestLine {
+            method,
+            target,
+            path,
+            query,
+            version: version.to_string(),
+        })
     }
 
-    fn parse_headers(&self, lines: std::str::Lines<'_>) -> HashMap<String, String> {
-        let mut headers = HashMap::new();
+    fn parse_headers<'a>(
+        &self,
+        lines: impl Iterator<Item = &'a str>,
+    ) -> Result<HttpHeaders, ParseError> {
+        let mut headers = HttpHeaders::new();
         for l in lines {
-            let header: Vec<&str> = l.split(':').collect();
-            let key = header.get(0).unwrap_or(&"").to_string();
-            let value = header.get(1).unwrap_or(&"").to_string();
-            if !key.is_empty() && !value.is_empty() {
-                headers.insert(key, value);
+            // values may contain ':' themselves (`Host: example.com:8080`), only the first one separates
+            let Some((key, value)) = l.split_once(':') else {
+                // obs-fold continuation lines are refused as well (RFC 9112, section 5.2)
+                return Err(ParseError::Malformed("header field without a colon"));
+            };
+            // whitespace before the colon could make a field, e.g. `Transfer-Encoding`,
+            // mean something else to another server on the way (RFC 9112, section 5.1)
+            if !is_token(key) {
+                return Err(ParseError::Malformed("invalid header field name"));
+            }
+            headers.append(key, value.trim());
+            if headers.len() > self.limits.max_headers {
+                return Err(ParseError::HeadersTooLarge);
             }
         }
-        headers
+        Ok(headers)
     }
 
-    async fn parse_request_body(
-        &self,
-        socket: &mut TcpStream,
-        req: Vec<&str>,
-        headers: &HashMap<String, String>,
-    ) -> String {
-        let content_length = match headers.get("Content-Length") {
-            Some(v) => v.parse::<u32>().unwrap(),
-            _ => 0,
-        };
-
-        if content_length > 0 {
-            let mut req_body = req.get(1).unwrap_or(&"").to_string();
-            if self.logging_enabled() {
-                // println!(
-                //     "Content-Length is {content_length} and parsed body size is {}",
-                //     req_body.len()
-                // );
-            }
-
-            let mut buf = [0u8; 1024];
-
-            while (req_body.len() as u32) < content_length {
-                match socket.read(&mut buf).await {
-                    Ok(c) => {
-                        let st = std::str::from_utf8(&buf[0..c]).unwrap();
-                        req_body.push_str(st);
-                    }
-                    Err(_) => {
-                        // println!(
-                        //     "There was an error while parsing the request body! Error: {err:?}"
-                        // );
-                    }
-                }
+    async fn parse_request_body<S: AsyncRead + Unpin>(
+        &mut self,
+        socket: &mut S,
+        headers: &HttpHeaders,
+    ) -> Result<(Bytes, HttpHeaders), ParseError> {
+        self.deadline = deadline_after(self.timeouts.body_read);
+        // Transfer-Encoding takes precedence over Content-Length (RFC 9112, section 6.3)
+        if headers.contains("Transfer-Encoding") {
+            // a request framed both ways is a smuggling attempt (RFC 9112, section 6.3)
+            if headers.contains("Content-Length") {
+                return Err(ParseError::Malformed(
+                    "both Transfer-Encoding and Content-Length",
+                ));
             }
-            req_body
-        } else {
-            String::new()
+            // no other coding is decoded, so anything but a lone `chunked` would be
+            // stored still encoded
+            let mut codings = headers
+                .get_all("Transfer-Encoding")
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .filter(|coding| !coding.is_empty());
+            let is_chunked = codings
+                .next()
+                .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"));
+            if !is_chunked || codings.next().is_some() {
+                return Err(ParseError::UnsupportedTransferCoding);
+            }
+            return self.parse_chunked_body(socket).await;
+        }
+
+        let content_length = content_length(headers)?.unwrap_or(0);
+        // refuse before reading anything, the client announced too much
+        if content_length > self.limits.max_body_size {
+            return Err(ParseError::PayloadTooLarge);
+        }
+
+        if self.logging_enabled() {
+            // println!(
+            //     "Content-Length is {content_length} and buffered body size is {}",
+            //     self.buffer.len()
+            // );
         }
+
+        while self.buffer.len() < content_length {
+            self.fill_buffer(socket).await?;
+        }
+
+        Ok((
+            self.buffer.split_to(content_length).freeze(),
+            HttpHeaders::new(),
+        ))
     }
 
-    fn parse_path_params(&self, target: &String, router: &HttpRouter) -> HashMap<String, String> {
-        let mut params = HashMap::new();
+    async fn parse_chunked_body<S: AsyncRead + Unpin>(
+        &mut self,
+        socket: &mut S,
+    ) -> Result<(Bytes, HttpHeaders), ParseError> {
+        let mut body = BytesMut::new();
 
-        for (path, route) in router.get_routes() {
-            if route.get_path_params().is_empty() {
-                continue;
+        loop {
+            let line = self
+                .read_line(socket, || {
+                    ParseError::Malformed("chunk size line is too long")
+                })
+                .await?;
+            // chunk-size [ ";" chunk-ext ], extensions carry no meaning for us
+            let size = line.split(';').next().unwrap_or("").trim();
+            // from_str_radix alone would accept a leading sign
+            if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return Err(ParseError::Malformed("invalid chunk size"));
             }
-            let reg = Regex::new(path.as_str()).unwrap();
-            if reg.is_match(&target) {
-                // println!("found a match for {} : {}", target, path);
-                let tokens: Vec<String> = target
-                    .split("/")
-                    .filter(|x| !x.is_empty())
-                    .map(|x| x.to_string())
-                    .collect();
-                // dbg!(&tokens);
-                for (index, param) in route.get_path_params() {
-                    // println!("index: {}, key: {}", index, param);
-                    params.insert(param.clone(), tokens.get(*index - 1).unwrap().clone());
-                }
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| ParseError::Malformed("invalid chunk size"))?;
+
+            if size == 0 {
+                break;
+            }
+            // checked before any arithmetic, sizes come straight from the client
+            if size > self.limits.max_body_size - body.len() {
+                return Err(ParseError::PayloadTooLarge);
+            }
+            let chunk_end = size
+                .checked_add(2)
+                .ok_or(ParseError::Malformed("invalid chunk size"))?;
+
+            while self.buffer.len() < chunk_end {
+                self.fill_buffer(socket).await?;
+            }
+            body.extend_from_slice(&self.buffer[..size]);
+            if &self.buffer[size..chunk_end] != b"\r\n" {
+                return Err(ParseError::Malformed("chunk data not terminated by CRLF"));
+            }
+            self.buffer.advance(chunk_end);
+        }
+
+        let mut trailer_lines = Vec::new();
+        let mut trailer_bytes = 0;
+        loop {
+            let line = self
+                .read_line(socket, || ParseError::HeadersTooLarge)
+                .await?;
+            if line.is_empty() {
+                break;
+            }
+            trailer_bytes += line.len() + 2;
+            if trailer_bytes > self.limits.max_header_bytes {
+                return Err(ParseError::HeadersTooLarge);
             }
+            trailer_lines.push(line);
+        }
+        let trailers = self.parse_headers(trailer_lines.iter().map(String::as_str))?;
+
+        Ok((body.freeze(), trailers))
+    }
+
+    /// Reads a single CRLF terminated line from the connection, without the CRLF.
+    /// Lines longer than the header limit are refused with `too_long()`.
+    async fn read_line<S: AsyncRead + Unpin>(
+        &mut self,
+        socket: &mut S,
+        too_long: fn() -> ParseError,
+    ) -> Result<String, ParseError> {
+        loop {
+            if let Some(pos) = find(&self.buffer, b"\r\n") {
+                let line = self.buffer.split_to(pos + 2);
+                return String::from_utf8(line[..pos].to_vec())
+                    .map_err(|_| ParseError::Malformed("line is not valid UTF-8"));
+            }
+            if self.buffer.len() > self.limits.max_header_bytes {
+                return Err(too_long());
+            }
+            self.fill_buffer(socket).await?;
+        }
+    }
+
+    async fn fill_buffer<S: AsyncRead + Unpin>(
+        &mut self,
+        socket: &mut S,
+    ) -> Result<usize, ParseError> {
+        let read = socket.read_buf(&mut self.buffer);
+        let size = match self.deadline {
+            Some(deadline) => timeout_at(deadline, read)
+                .await
+                .map_err(|_| ParseError::Timeout)??,
+            None => read.await?,
+        };
+        if size == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Connection closed before the request was complete",
+            )
+            .into());
+        }
+        Ok(size)
+    }
+}
+
+/// The `Content-Length` of a request, refused unless every field (and list member)
+/// carries the same digits-only value, since framing on one of differing values
+/// allows request smuggling (RFC 9112, section 6.3).
+fn content_length(headers: &HttpHeaders) -> Result<Option<usize>, ParseError> {
+    let invalid = || ParseError::Malformed("invalid Content-Length");
+    let mut length = None;
+    for value in headers
+        .get_all("Content-Length")
+        .flat_map(|value| value.split(','))
+    {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(invalid());
         }
+        let value = value.parse::<usize>().map_err(|_| invalid())?;
+        if length.is_some_and(|length| length != value) {
+            return Err(invalid());
+        }
+        length = Some(value);
+    }
+    Ok(length)
+}
+
+/// Whether `name` is a non-empty RFC 9110 token, as field names must be.
+fn is_token(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+fn is_http1_version(version: &str) -> bool {
+    version
+        .strip_prefix("HTTP/1.")
+        .is_some_and(|minor| minor.len() == 1 && minor.as_bytes()[0].is_ascii_digit())
+}
 
-        params
+/// The path of an absolute-form target (`http://host/path`), `/` when it has none.
+fn absolute_form_path(target: &str) -> Option<&str> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
     }
+    match rest.find('/') {
+        Some(start) if start > 0 => Some(&rest[start..]),
+        None if !rest.is_empty() => Some("/"),
+        _ => None,
+    }
+}
+
+fn deadline_after(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.map(|timeout| Instant::now() + timeout)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
 }
 
 impl Logging for Parser {
@@ -222,3 +533,200 @@ impl Logging for Parser {
         self.logging_enabled
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(input: &[u8]) -> Result<HttpRequest, ParseError> {
+        let mut socket = input;
+        Parser::new().parse_http_request(&mut socket).await
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_request_lines() {
+        for line in [
+            "GET",
+            "GET /",
+            "GET / HTTP/1.1 extra",
+            "GET  / HTTP/1.1",
+            "GET index.html HTTP/1.1",
+            "GET ftp://host/ HTTP/1.1",
+            "GET / HTTP/2.0",
+            "GET / HTTP/1.10",
+            "GET / http/1.1",
+            "FETCH / HTTP/1.1",
+        ] {
+            let request = format!("{line}\r\n\r\n");
+            let err = parse(request.as_bytes()).await.unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BadRequest, "{line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_request_target_forms() {
+        for (line, path) in [
+            ("GET /echo/abc?x=1 HTTP/1.0", "/echo/abc"),
+            ("OPTIONS * HTTP/1.1", "*"),
+            ("GET http://example.com/files/a?x=1 HTTP/1.1", "/files/a"),
+            ("GET HTTP://example.com HTTP/1.1", "/"),
+        ] {
+            let request = format!("{line}\r\n\r\n");
+            let req = parse(request.as_bytes()).await.unwrap();
+            assert_eq!(req.path, path, "{line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_header_fields() {
+        for field in [
+            "Transfer-Encoding : chunked",
+            " Host: x",
+            "X-Folded: a\r\n  continued",
+            "No colon here",
+            ": empty name",
+            "Bad\tName: x",
+            "Bad(Name): x",
+        ] {
+            let request = format!("POST / HTTP/1.1\r\nContent-Length: 3\r\n{field}\r\n\r\nabc");
+            let err = parse(request.as_bytes()).await.unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BadRequest, "{field:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_trailer_fields() {
+        let err = parse(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              0\r\nChecksum : abc\r\n\r\n",
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn parses_content_length_body() {
+        let req = parse(b"POST /files/a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello")
+            .await
+            .unwrap();
+        assert_eq!(req.method, HttpRequestMethod::POST);
+        assert_eq!(req.path, "/files/a");
+        assert_eq!(&req.body[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn decodes_chunked_body_with_extensions_and_trailers() {
+        let req = parse(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              4;name=value\r\nWiki\r\n5\r\npedia\r\n0\r\nChecksum: abc\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        assert_eq!(&req.body[..], b"Wikipedia");
+        assert_eq!(req.trailers.get("Checksum"), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn keeps_pipelined_requests_buffered() {
+        let mut socket: &[u8] = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            3\r\nabc\r\n0\r\n\r\nGET /b HTTP/1.1\r\nHost: x\r\n\r\n";
+        let mut parser = Parser::new();
+
+        let first = parser.parse_http_request(&mut socket).await.unwrap();
+        assert_eq!(first.path, "/a");
+        assert_eq!(&first.body[..], b"abc");
+
+        let second = parser.parse_http_request(&mut socket).await.unwrap();
+        assert_eq!(second.path, "/b");
+        assert_eq!(second.headers.get("Host"), Some("x"));
+
+        let closed = parser.parse_http_request(&mut socket).await.unwrap_err();
+        assert!(closed.is_closed());
+    }
+
+    #[tokio::test]
+    async fn rejects_transfer_codings_other_than_chunked() {
+        for coding in ["gzip, chunked", "chunked, chunked", "gzip"] {
+            let request =
+                format!("POST / HTTP/1.1\r\nTransfer-Encoding: {coding}\r\n\r\n0\r\n\r\n");
+            let err = parse(request.as_bytes()).await.unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::NotImplemented, "{coding}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_signed_chunk_size() {
+        let err =
+            parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+3\r\nabc\r\n0\r\n\r\n")
+                .await
+                .unwrap_err();
+        assert!(matches!(err, ParseError::Malformed(_)));
+    }
+
+    #[tokio::test]
+    async fn rejects_chunk_without_crlf() {
+        let err =
+            parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcX\r\n0\r\n\r\n")
+                .await
+                .unwrap_err();
+        assert!(matches!(err, ParseError::Malformed(_)));
+    }
+
+    #[tokio::test]
+    async fn rejects_overflowing_chunk_size() {
+        let err = parse(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              1\r\na\r\nffffffffffffffff\r\n",
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PayloadTooLarge);
+    }
+
+    #[tokio::test]
+    async fn rejects_chunked_body_over_limit() {
+        let mut socket: &[u8] =
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nabcd\r\n3\r\nefg\r\n0\r\n\r\n";
+        let mut parser = Parser::with_limits(RequestLimits {
+            max_body_size: 6,
+            ..Default::default()
+        });
+        let err = parser.parse_http_request(&mut socket).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PayloadTooLarge);
+    }
+
+    #[tokio::test]
+    async fn rejects_conflicting_content_lengths() {
+        for lengths in [
+            "Content-Length: 3\r\nContent-Length: 10",
+            "Content-Length: 3, 10",
+            "Content-Length: +3",
+            "Content-Length: -3",
+            "Content-Length: ",
+        ] {
+            let request = format!("POST / HTTP/1.1\r\n{lengths}\r\n\r\nabcdefghij");
+            let err = parse(request.as_bytes()).await.unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BadRequest, "{lengths}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_transfer_encoding_with_content_length() {
+        let err = parse(
+            b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n\
+              0\r\n\r\n",
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn accepts_repeated_identical_content_lengths() {
+        let req = parse(b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3, 3\r\n\r\nabc")
+            .await
+            .unwrap();
+        assert_eq!(&req.body[..], b"abc");
+    }
+}
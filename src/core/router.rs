@@ -1,173 +1,481 @@
-use regex::Regex;
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, sync::Arc};
 
-use crate::core::server::Context;
+use crate::core::endpoint::{Handler, SharedHandler};
+use crate::core::middleware::{Middleware, SharedMiddleware};
+use crate::core::static_files::StaticFiles;
+use crate::core::websocket::{UpgradeHandler, WebSocketHandler};
 use crate::types::method::*;
 use crate::types::request::*;
-use crate::types::response::*;
 
-#[derive(Debug)]
+/// Routes requests to handlers using a tree of path segments.
+///
+/// Patterns are made of `/` separated segments which can be static (`files`),
+/// a named parameter matching exactly one segment (`{filename}`) or a trailing
+/// wildcard matching the rest of the path (`{*path}`). Patterns are compiled into
+/// the tree once, at registration, and when several of them match a path the most
+/// specific one wins: static segments over parameters over wildcards.
+#[derive(Debug, Default)]
 pub struct HttpRouter {
-    routes: HashMap<String, Route>,
+    root: RouteNode,
 }
 
 impl HttpRouter {
     pub fn new() -> Self {
         Self {
-            routes: HashMap::new(),
+            root: RouteNode::default(),
         }
     }
 
-    pub fn get(&mut self, path: &str, handler: fn(HttpRequest, &Context) -> HttpResponse) {
+    pub fn get(&mut self, path: &str, handler: impl Handler) {
         self.register(HttpRequestMethod::GET, path, handler);
     }
 
-    pub fn post(&mut self, path: &str, handler: fn(HttpRequest, &Context) -> HttpResponse) {
+    /// Registers an explicit `HEAD` handler. Without one, `HEAD` requests run the
+    /// `GET` handler and the body is dropped when writing the response.
+    pub fn head(&mut self, path: &str, handler: impl Handler) {
+        self.register(HttpRequestMethod::HEAD, path, handler);
+    }
+
+    pub fn post(&mut self, path: &str, handler: impl Handler) {
         self.register(HttpRequestMethod::POST, path, handler);
     }
 
-    pub fn patch(&mut self, path: &str, handler: fn(HttpRequest, &Context) -> HttpResponse) {
+    pub fn patch(&mut self, path: &str, handler: impl Handler) {
         self.register(HttpRequestMethod::PATCH, path, handler);
     }
 
-    pub fn put(&mut self, path: &str, handler: fn(HttpRequest, &Context) -> HttpResponse) {
+    pub fn put(&mut self, path: &str, handler: impl Handler) {
         self.register(HttpRequestMethod::PUT, path, handler);
     }
 
-    pub fn options(&mut self, path: &str, handler: fn(HttpRequest, &Context) -> HttpResponse) {
+    pub fn options(&mut self, path: &str, handler: impl Handler) {
         self.register(HttpRequestMethod::OPTIONS, path, handler);
     }
 
-    pub fn delete(&mut self, path: &str, handler: fn(HttpRequest, &Context) -> HttpResponse) {
+    pub fn delete(&mut self, path: &str, handler: impl Handler) {
         self.register(HttpRequestMethod::DELETE, path, handler);
     }
 
-    fn register(
-        &mut self,
-        method: HttpRequestMethod,
-        path: &str,
-        handler: fn(HttpRequest, &Context) -> HttpResponse,
-    ) {
-        let key = String::from(path.trim());
-        let mut path_params = Vec::new();
-
-        let parts: Vec<(usize, String)> = key
-            .split("/")
-            .enumerate()
-            .map(|(index, str)| (index, str.to_string()))
-            .filter(|(_, str)| !str.is_empty())
-            .collect();
-
-        let mut path_regex = String::from("");
-        if !parts.is_empty() {
-            let mut has_params = false;
-            for param in &parts {
-                if param.1.starts_with("{") && param.1.ends_with("}") {
-                    has_params = true;
-                    break;
-                }
-            }
-            if has_params {
-                for param in parts {
-                    path_regex.push_str("/");
-                    if param.1.starts_with("{") && param.1.ends_with("}") {
-                        path_params.push((
-                            param.0,
-                            param
-                                .1
-                                .strip_prefix("{")
-                                .unwrap()
-                                .strip_suffix("}")
-                                .unwrap()
-                                .to_string(),
-                        ));
-                        path_regex.push_str(r"([a-zA-Z_\-0-9\.]+)");
-                    } else {
-                        path_regex.push_str(param.1.as_str());
-                    }
-                }
-                path_regex.insert(0, '^');
-                path_regex.push_str("$");
+    /// Registers a WebSocket endpoint: `GET` requests to `path` performing the opening
+    /// handshake are switched to the WebSocket protocol and served by `handler`.
+    pub fn websocket(&mut self, path: &str, handler: impl WebSocketHandler) {
+        self.register(HttpRequestMethod::GET, path, UpgradeHandler::new(handler));
+    }
+
+    /// Serves the files of `files` below `prefix`, e.g. `/assets/css/app.css` from
+    /// `css/app.css` when mounted at `/assets`. The route pattern is `{prefix}/{*path}`.
+    pub fn static_files(&mut self, prefix: &str, files: StaticFiles) {
+        let pattern = format!("{}/{{*path}}", prefix.trim_end_matches('/'));
+        self.register(HttpRequestMethod::GET, &pattern, files);
+    }
+
+    /// Registers `handler` for `method` on the route pattern `path`.
+    ///
+    /// Panics if the pattern is malformed or if its parameters conflict with the
+    /// names used by a previously registered pattern at the same position.
+    pub fn register(&mut self, method: HttpRequestMethod, path: &str, handler: impl Handler) {
+        self.route_mut(path)
+            .handlers
+            .insert(method, Arc::new(handler));
+    }
+
+    /// Attaches `middleware` to every method of the route pattern `path`.
+    ///
+    /// Route middlewares run after the server-wide ones, in the order they were added.
+    /// Panics if no handler was registered for `path` yet.
+    pub fn middleware(&mut self, path: &str, middleware: impl Middleware) {
+        self.registered_route_mut(path)
+            .middlewares
+            .push(Arc::new(middleware));
+    }
+
+    /// Sends the responses of the route pattern `path` uncompressed, e.g. for bodies
+    /// which are already compressed or must not be buffered by the client. Panics if
+    /// no handler was registered for `path` yet.
+    pub fn disable_compression(&mut self, path: &str) {
+        self.registered_route_mut(path).compress = false;
+    }
+
+    /// The route of the pattern `path`, which must have a handler already: creating
+    /// it here would turn a path answered with 404 into one answered with 405.
+    fn registered_route_mut(&mut self, path: &str) -> &mut Route {
+        let pattern = path.trim();
+        let mut node = &mut self.root;
+        for segment in pattern.split('/').filter(|s| !s.is_empty()) {
+            let next = match Segment::parse(segment) {
+                Segment::Static(name) => node.children.get_mut(name),
+                Segment::Param(name) => node
+                    .param
+                    .as_deref_mut()
+                    .filter(|(param, _)| param == name)
+                    .map(|(_, child)| child),
+                Segment::Wildcard(name) => node
+                    .wildcard
+                    .as_deref_mut()
+                    .filter(|(param, _)| param == name)
+                    .map(|(_, child)| child),
+            };
+            match next {
+                Some(child) => node = child,
+                None => panic!("route '{}' has no handler registered", pattern),
             }
         }
+        match node.route.as_mut() {
+            Some(route) if !route.handlers.is_empty() => route,
+            _ => panic!("route '{}' has no handler registered", pattern),
+        }
+    }
 
-        // dbg!(&path_params);
-        // dbg!(&path_regex);
-        if path_regex.is_empty() {
-            match self.routes.get_mut(&key) {
-                Some(route) => {
-                    route.handlers.insert(method, handler);
-                    route.path_params = path_params;
-                }
-                None => {
-                    let mut route = Route::new(method, handler);
-                    route.path_params = path_params;
-                    self.routes.insert(key, route);
-                }
+    fn route_mut(&mut self, path: &str) -> &mut Route {
+        let pattern = path.trim();
+        let segments: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+
+        let mut node = &mut self.root;
+        for (index, segment) in segments.iter().enumerate() {
+            let segment = Segment::parse(segment);
+            if let Segment::Param("") | Segment::Wildcard("") = segment {
+                panic!("route '{}' has a parameter without a name", pattern);
             }
-        } else {
-            match self.routes.get_mut(&path_regex) {
-                Some(route) => {
-                    route.handlers.insert(method, handler);
-                    route.path_params = path_params;
+            node = match segment {
+                Segment::Static(name) => node.children.entry(name.to_string()).or_default(),
+                Segment::Param(name) => {
+                    let entry = node
+                        .param
+                        .get_or_insert_with(|| Box::new((name.to_string(), RouteNode::default())));
+                    let (param, child) = &mut **entry;
+                    if param != name {
+                        panic!(
+                            "route '{}' names parameter '{{{}}}' where another route uses '{{{}}}'",
+                            pattern, name, param
+                        );
+                    }
+                    child
                 }
-                None => {
-                    let mut route = Route::new(method, handler);
-                    route.path_params = path_params;
-                    self.routes.insert(path_regex, route);
+                Segment::Wildcard(name) => {
+                    if index != segments.len() - 1 {
+                        panic!(
+                            "route '{}': wildcard '{{*{}}}' must be the last segment",
+                            pattern, name
+                        );
+                    }
+                    let entry = node
+                        .wildcard
+                        .get_or_insert_with(|| Box::new((name.to_string(), RouteNode::default())));
+                    let (param, child) = &mut **entry;
+                    if param != name {
+                        panic!(
+                            "route '{}' names wildcard '{{*{}}}' where another route uses '{{*{}}}'",
+                            pattern, name, param
+                        );
+                    }
+                    child
                 }
-            }
+            };
         }
+
+        node.route.get_or_insert_with(|| Route::new(pattern))
     }
 
-    pub fn get_handler(
-        &self,
-        req: &HttpRequest,
-    ) -> Option<&fn(HttpRequest, &Context) -> HttpResponse> {
-        match self.routes.get(&req.target) {
-            Some(route) => route.handlers.get(&req.method),
-            None => {
-                for (path, route) in &self.routes {
-                    if route.path_params.is_empty() {
-                        continue;
-                    }
-                    let reg = Regex::new(path.as_str()).unwrap();
-                    if reg.is_match(&req.target) {
-                        // println!("found a match for {} : {}", req.target, path);
-                        return route.handlers.get(&req.method);
-                    }
-                }
+    /// Finds the route matching `path`, together with the values of its parameters.
+    pub fn find_route(&self, path: &str) -> Option<(&Route, HashMap<String, String>)> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut params = Vec::new();
 
-                None
+        self.root
+            .find(&segments, &mut params)
+            .map(|route| (route, params.into_iter().collect()))
+    }
+
+    /// Finds the route and handler registered for the request's method and path,
+    /// together with the values of the path parameters.
+    pub fn get_handler(&self, req: &HttpRequest) -> Option<RouteMatch<'_>> {
+        let (route, path_params) = self.find_route(&req.path)?;
+        let handler = route.get_handler(&req.method)?;
+        Some(RouteMatch {
+            route,
+            handler,
+            path_params,
+        })
+    }
+}
+
+pub struct RouteMatch<'a> {
+    pub route: &'a Route,
+    pub handler: &'a SharedHandler,
+    pub path_params: HashMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+struct RouteNode {
+    route: Option<Route>,
+    children: HashMap<String, RouteNode>,
+    param: Option<Box<(String, RouteNode)>>,
+    wildcard: Option<Box<(String, RouteNode)>>,
+}
+
+impl RouteNode {
+    fn find<'a>(
+        &'a self,
+        segments: &[&str],
+        params: &mut Vec<(String, String)>,
+    ) -> Option<&'a Route> {
+        let Some((segment, rest)) = segments.split_first() else {
+            if self.route.is_some() {
+                return self.route.as_ref();
             }
+            // a wildcard also matches an empty remainder
+            let (name, child) = self.wildcard.as_deref()?;
+            let route = child.route.as_ref()?;
+            params.push((name.clone(), String::new()));
+            return Some(route);
+        };
+
+        if let Some(child) = self.children.get(*segment)
+            && let Some(route) = child.find(rest, params)
+        {
+            return Some(route);
         }
+
+        if let Some(param) = &self.param {
+            let (name, child) = param.as_ref();
+            params.push((name.clone(), segment.to_string()));
+            if let Some(route) = child.find(rest, params) {
+                return Some(route);
+            }
+            params.pop();
+        }
+
+        let (name, child) = self.wildcard.as_deref()?;
+        let route = child.route.as_ref()?;
+        params.push((name.clone(), segments.join("/")));
+        Some(route)
     }
+}
+
+enum Segment<'a> {
+    Static(&'a str),
+    Param(&'a str),
+    Wildcard(&'a str),
+}
 
-    pub fn get_routes(&self) -> &HashMap<String, Route> {
-        &self.routes
+impl<'a> Segment<'a> {
+    fn parse(segment: &'a str) -> Self {
+        match segment
+            .strip_prefix('{')
+            .and_then(|inner| inner.strip_suffix('}'))
+        {
+            Some(inner) => match inner.strip_prefix('*') {
+                Some(name) => Segment::Wildcard(name),
+                None => Segment::Param(inner),
+            },
+            None => Segment::Static(segment),
+        }
     }
 }
 
-#[derive(Debug)]
 pub struct Route {
-    handlers: HashMap<HttpRequestMethod, fn(HttpRequest, &Context) -> HttpResponse>,
-    path_params: Vec<(usize, String)>,
+    pattern: String,
+    handlers: HashMap<HttpRequestMethod, SharedHandler>,
+    middlewares: Vec<SharedMiddleware>,
+    compress: bool,
 }
 
 impl Route {
-    pub fn new(
-        method: HttpRequestMethod,
-        handler: fn(HttpRequest, &Context) -> HttpResponse,
-    ) -> Self {
-        let mut handlers = HashMap::new();
-        handlers.insert(method, handler);
+    pub fn new(pattern: &str) -> Self {
         Self {
-            handlers,
-            path_params: Vec::new(),
+            pattern: pattern.to_string(),
+            handlers: HashMap::new(),
+            middlewares: Vec::new(),
+            compress: true,
         }
     }
 
-    pub fn get_path_params(&self) -> &Vec<(usize, String)> {
-        &self.path_params
+    pub fn get_pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn get_handler(&self, method: &HttpRequestMethod) -> Option<&SharedHandler> {
+        match self.handlers.get(method) {
+            None if *method == HttpRequestMethod::HEAD => {
+                self.handlers.get(&HttpRequestMethod::GET)
+            }
+            handler => handler,
+        }
+    }
+
+    pub fn get_middlewares(&self) -> &[SharedMiddleware] {
+        &self.middlewares
+    }
+
+    pub fn compression_enabled(&self) -> bool {
+        self.compress
+    }
+
+    /// Lists the methods this route answers, in a stable order. `OPTIONS` is always
+    /// included since the server answers it for every route, and so is `HEAD` when
+    /// there is a `GET` handler.
+    pub fn allowed_methods(&self) -> Vec<HttpRequestMethod> {
+        let mut methods: Vec<HttpRequestMethod> = self.handlers.keys().copied().collect();
+        if !methods.contains(&HttpRequestMethod::OPTIONS) {
+            methods.push(HttpRequestMethod::OPTIONS);
+        }
+        if methods.contains(&HttpRequestMethod::GET) && !methods.contains(&HttpRequestMethod::HEAD)
+        {
+            methods.push(HttpRequestMethod::HEAD);
+        }
+        methods.sort();
+        methods
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("pattern", &self.pattern)
+            .field("methods", &self.handlers.keys().collect::<Vec<_>>())
+            .field("middlewares", &self.middlewares.len())
+            .field("compress", &self.compress)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{middleware::Next, server::Context};
+    use crate::types::response::HttpResponse;
+
+    async fn ok(_req: HttpRequest, _ctx: Context) -> HttpResponse {
+        HttpResponse::builder().build()
+    }
+
+    async fn pass(req: HttpRequest, ctx: Context, next: Next) -> HttpResponse {
+        next.run(req, ctx).await
+    }
+
+    #[test]
+    fn middleware_attaches_to_registered_route() {
+        let mut router = HttpRouter::new();
+        router.get("/files/{filename}", ok);
+        router.middleware("/files/{filename}", pass);
+
+        let (route, params) = router.find_route("/files/a.txt").unwrap();
+        assert_eq!(route.get_middlewares().len(), 1);
+        assert_eq!(params["filename"], "a.txt");
+    }
+
+    #[test]
+    #[should_panic(expected = "has no handler registered")]
+    fn middleware_requires_registered_route() {
+        let mut router = HttpRouter::new();
+        router.get("/files/{filename}", ok);
+        router.middleware("/file/{filename}", pass);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no handler registered")]
+    fn disable_compression_requires_registered_route() {
+        let mut router = HttpRouter::new();
+        router.get("/events", ok);
+        router.disable_compression("/event");
+    }
+
+    fn matched(router: &HttpRouter, path: &str) -> Option<(String, HashMap<String, String>)> {
+        router
+            .find_route(path)
+            .map(|(route, params)| (route.get_pattern().to_string(), params))
+    }
+
+    #[test]
+    fn static_beats_param_beats_wildcard() {
+        let mut router = HttpRouter::new();
+        router.get("/files/{*path}", ok);
+        router.get("/files/{name}", ok);
+        router.get("/files/index", ok);
+
+        let (pattern, params) = matched(&router, "/files/index").unwrap();
+        assert_eq!(pattern, "/files/index");
+        assert!(params.is_empty());
+
+        let (pattern, params) = matched(&router, "/files/a.txt").unwrap();
+        assert_eq!(pattern, "/files/{name}");
+        assert_eq!(params["name"], "a.txt");
+
+        let (pattern, params) = matched(&router, "/files/a/b.txt").unwrap();
+        assert_eq!(pattern, "/files/{*path}");
+        assert_eq!(params["path"], "a/b.txt");
+    }
+
+    #[test]
+    fn find_route_backtracks() {
+        let mut router = HttpRouter::new();
+        router.get("/users/me/settings", ok);
+        router.get("/users/{id}/posts", ok);
+        router.get("/{*rest}", ok);
+
+        // "me" matches the static branch, which has no "posts" child
+        let (pattern, params) = matched(&router, "/users/me/posts").unwrap();
+        assert_eq!(pattern, "/users/{id}/posts");
+        assert_eq!(params["id"], "me");
+        assert!(!params.contains_key("rest"));
+
+        // neither the static nor the parameter branch matches, so the wildcard does
+        // without keeping parameters of the abandoned branches
+        let (pattern, params) = matched(&router, "/users/me/likes").unwrap();
+        assert_eq!(pattern, "/{*rest}");
+        assert_eq!(params.len(), 1);
+        assert_eq!(params["rest"], "users/me/likes");
+    }
+
+    #[test]
+    fn wildcard_matches_empty_remainder() {
+        let mut router = HttpRouter::new();
+        router.get("/static/{*path}", ok);
+
+        for path in ["/static", "/static/"] {
+            let (pattern, params) = matched(&router, path).unwrap();
+            assert_eq!(pattern, "/static/{*path}");
+            assert_eq!(params["path"], "");
+        }
+        assert!(matched(&router, "/stat").is_none());
+    }
+
+    #[test]
+    fn route_without_wildcard_does_not_match_longer_path() {
+        let mut router = HttpRouter::new();
+        router.get("/files/{name}", ok);
+
+        assert!(matched(&router, "/files").is_none());
+        assert!(matched(&router, "/files/a/b").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "names parameter '{name}' where another route uses '{id}'")]
+    fn conflicting_parameter_names_panic() {
+        let mut router = HttpRouter::new();
+        router.get("/users/{id}", ok);
+        router.post("/users/{name}/posts", ok);
+    }
+
+    #[test]
+    #[should_panic(expected = "names wildcard '{*file}' where another route uses '{*path}'")]
+    fn conflicting_wildcard_names_panic() {
+        let mut router = HttpRouter::new();
+        router.get("/static/{*path}", ok);
+        router.get("/static/{*file}", ok);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be the last segment")]
+    fn wildcard_must_be_last() {
+        let mut router = HttpRouter::new();
+        router.get("/files/{*path}/meta", ok);
+    }
+
+    #[test]
+    #[should_panic(expected = "has a parameter without a name")]
+    fn unnamed_parameter_panics() {
+        let mut router = HttpRouter::new();
+        router.get("/files/{}", ok);
     }
 }
@@ -1,19 +1,91 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Debug,
+    io, mem,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
 
-use super::{handler::HttpRequestHandler, logging::Logging, router::HttpRouter};
+use super::{
+    encoding::{CompressionPolicy, Encoder, Encodings},
+    handler::HttpRequestHandler,
+    listener::Listener,
+    logging::Logging,
+    middleware::{Middleware, SharedMiddleware},
+    mime::MimeTypes,
+    parser::{RequestLimits, Timeouts},
+    router::HttpRouter,
+    shutdown::{ShutdownHandle, termination_signal},
+};
 
-use tokio::net::TcpListener;
+#[cfg(unix)]
+use {super::listener::unix::UnixSocketListener, std::path::Path};
 
+#[cfg(feature = "tls")]
+use super::tls::TlsConfig;
+
+use futures_util::{StreamExt, stream};
+use tokio::{
+    net::{TcpListener, ToSocketAddrs},
+    task::JoinSet,
+};
+
+/// Shared state handed to every handler.
+///
+/// Besides the `workdir`, it holds at most one application defined state value per
+/// type, e.g. an `AppState` with a database pool, looked up with [`Context::state`],
+/// and the [`MimeTypes`] used to serve files.
 #[derive(Debug, Default, Clone)]
 pub struct Context {
     pub workdir: String,
+    states: Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+    mime_types: Arc<MimeTypes>,
+}
+
+impl Context {
+    pub fn new(workdir: impl Into<String>) -> Self {
+        Self {
+            workdir: workdir.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Stores `state`, replacing any previous state of the same type.
+    pub fn insert_state<T: Send + Sync + 'static>(&mut self, state: T) {
+        Arc::make_mut(&mut self.states).insert(TypeId::of::<T>(), Arc::new(state));
+    }
+
+    /// Returns the state of type `T`, if one was stored.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.states
+            .get(&TypeId::of::<T>())
+            .and_then(|state| state.downcast_ref::<T>())
+    }
+
+    pub fn mime_types(&self) -> &MimeTypes {
+        &self.mime_types
+    }
+
+    /// The [`MimeTypes`] to extend or override, e.g. with `insert("md", "text/plain")`.
+    pub fn mime_types_mut(&mut self) -> &mut MimeTypes {
+        Arc::make_mut(&mut self.mime_types)
+    }
 }
 
-#[derive(Debug)]
 pub struct HttpServer {
     logging_enabled: bool,
     router: Arc<HttpRouter>,
+    middlewares: Vec<SharedMiddleware>,
     context: Context,
+    limits: RequestLimits,
+    timeouts: Timeouts,
+    shutdown: ShutdownHandle,
+    shutdown_timeout: Option<Duration>,
+    listeners: Vec<Listener>,
+    encodings: Encodings,
+    compression: CompressionPolicy,
 }
 
 impl HttpServer {
@@ -21,24 +93,128 @@ impl HttpServer {
         Self {
             logging_enabled: false,
             router: Arc::new(router),
+            middlewares: Vec::new(),
             context: Context::default(),
+            limits: RequestLimits::default(),
+            timeouts: Timeouts::default(),
+            shutdown: ShutdownHandle::new(),
+            shutdown_timeout: Some(Duration::from_secs(30)),
+            listeners: Vec::new(),
+            encodings: Encodings::default(),
+            compression: CompressionPolicy::default(),
         }
     }
 
-    pub async fn listen(&self, port: u32) {
-        let listen = TcpListener::bind(format!("127.0.0.1:{}", port)).await.unwrap();
+    /// Binds a listener to `addr`, e.g. `"0.0.0.0:8080"` or `"[::1]:0"`, and returns
+    /// the address it is bound to. Port 0 picks a free port. Several listeners can be
+    /// bound, they all serve the same router once [`HttpServer::serve`] is called.
+    pub async fn bind(&mut self, addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        self.listeners.push(Listener::Tcp(listener));
+        Ok(local_addr)
+    }
+
+    /// Binds a listener to the Unix domain socket at `path`, served together with the
+    /// TCP listeners. See [`UnixSocketListener::bind`] for `mode` and for how an
+    /// existing socket file is handled. The socket file is removed on shutdown.
+    #[cfg(unix)]
+    pub fn bind_unix(&mut self, path: impl AsRef<Path>, mode: Option<u32>) -> io::Result<()> {
+        let listener = UnixSocketListener::bind(path, mode)?;
+        self.listeners.push(Listener::Unix(listener));
+        Ok(())
+    }
+
+    /// Binds a listener to `addr` which accepts HTTPS connections only, using the
+    /// certificates of `tls`. Reloading `tls` later also affects this listener.
+    #[cfg(feature = "tls")]
+    pub async fn bind_tls(
+        &mut self,
+        addr: impl ToSocketAddrs,
+        tls: &TlsConfig,
+    ) -> io::Result<SocketAddr> {
+        let acceptor = tls.acceptor().map_err(io::Error::other)?;
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        self.listeners.push(Listener::Tls(listener, acceptor));
+        Ok(local_addr)
+    }
+
+    /// The addresses of the TCP listeners bound so far.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(Listener::local_addr)
+            .collect()
+    }
+
+    /// Binds `addr` and serves it, see [`HttpServer::bind`] and [`HttpServer::serve`].
+    pub async fn listen(&mut self, addr: impl ToSocketAddrs) -> io::Result<()> {
+        self.bind(addr).await?;
+        self.serve().await
+    }
+
+    /// Accepts connections on every bound listener until shutdown is requested,
+    /// through Ctrl-C, SIGTERM or a [`ShutdownHandle`], then waits for the requests
+    /// in flight before returning.
+    pub async fn serve(&mut self) -> io::Result<()> {
+        if self.listeners.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no listener bound, call bind first",
+            ));
+        }
+        let mut incoming =
+            stream::select_all(mem::take(&mut self.listeners).into_iter().map(|listener| {
+                stream::unfold(listener, |listener| async move {
+                    let accepted = listener.accept().await;
+                    Some((accepted, listener))
+                })
+                .boxed()
+            }));
+        let middlewares: Arc<[SharedMiddleware]> = self.middlewares.clone().into();
+        let encodings = Arc::new(self.encodings.clone());
+        let mut connections = JoinSet::new();
+        let mut shutdown = self.shutdown.signal();
+        let terminate = termination_signal();
+        tokio::pin!(terminate);
 
         loop {
-            match listen.accept().await {
-                Ok((socket, _)) => {
+            let accepted = tokio::select! {
+                Some(accepted) = incoming.next() => accepted,
+                _ = shutdown.wait() => break,
+                _ = &mut terminate => {
+                    self.shutdown.shutdown();
+                    break;
+                }
+            };
+
+            match accepted {
+                Ok(pending) => {
                     let s_router = self.router.clone();
-                    let mut handler = HttpRequestHandler::new(s_router);
+                    let mut handler = HttpRequestHandler::new(s_router, middlewares.clone());
                     // pin!(socket);
+                    handler.set_limits(self.limits.clone());
+                    handler.set_timeouts(self.timeouts.clone());
+                    handler.set_shutdown(self.shutdown.signal());
+                    handler.set_encodings(encodings.clone());
+                    handler.set_compression(self.compression.clone());
                     if self.logging_enabled() {
                         handler.enable_logging();
                     }
                     let ctx = self.context.clone();
-                    tokio::spawn(async move {
+                    // a client stalling its TLS handshake is treated like one stalling its request head
+                    let handshake_timeout = self.timeouts.header_read;
+                    connections.spawn(async move {
+                        let connection = match handshake_timeout {
+                            Some(limit) => tokio::time::timeout(limit, pending)
+                                .await
+                                .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+                            None => pending.await,
+                        };
+                        let Ok(socket) = connection else {
+                            return;
+                        };
                         let _ = handler.handle_incoming_request(socket, &ctx).await;
                     });
                 }
@@ -48,11 +224,109 @@ impl HttpServer {
                     }
                 }
             }
+
+            // forget finished connections so the set only holds live ones
+            while connections.try_join_next().is_some() {}
+        }
+
+        drop(incoming);
+        if self.logging_enabled() {
+            println!("Shutting down, {} connection(s) open", connections.len());
         }
+
+        let drain = async { while connections.join_next().await.is_some() {} };
+        let drained = match self.shutdown_timeout {
+            Some(limit) => tokio::select! {
+                drained = tokio::time::timeout(limit, drain) => drained.is_ok(),
+                // a second signal does not wait for anyone
+                _ = termination_signal() => false,
+            },
+            None => tokio::select! {
+                _ = drain => true,
+                _ = termination_signal() => false,
+            },
+        };
+        if !drained && self.logging_enabled() {
+            println!("Shutdown deadline reached, aborting remaining connections");
+        }
+        // dropping the set aborts the connections still running
+        Ok(())
+    }
+
+    /// Sets the directory handed to handlers as [`Context::workdir`], keeping the
+    /// states and [`MimeTypes`] set before.
+    pub fn set_workdir(&mut self, workdir: impl Into<String>) {
+        self.context.workdir = workdir.into();
+    }
+
+    /// Adds a middleware run around every request, before the route middlewares.
+    pub fn middleware(&mut self, middleware: impl Middleware) {
+        self.middlewares.push(Arc::new(middleware));
+    }
+
+    /// Replaces the default [`RequestLimits`] applied to every request.
+    pub fn set_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
+    /// Replaces the default [`Timeouts`] applied to every connection.
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Offers `encoder` to clients accepting the content coding `scheme`, in addition
+    /// to the built-in ones. See [`Encodings::add`].
+    pub fn add_encoding_scheme(&mut self, scheme: &str, encoder: impl Encoder) {
+        self.encodings.add(scheme, encoder);
+    }
+
+    /// Replaces the content codings offered to clients, e.g. with [`Encodings::new`]
+    /// to never compress responses.
+    pub fn set_encodings(&mut self, encodings: Encodings) {
+        self.encodings = encodings;
+    }
+
+    /// Replaces the default [`CompressionPolicy`] deciding which responses are compressed.
+    pub fn set_compression(&mut self, compression: CompressionPolicy) {
+        self.compression = compression;
+    }
+
+    /// Returns a handle which stops [`HttpServer::listen`] when triggered.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Sets how long requests in flight may take to finish once shutdown started.
+    /// `None` waits for them however long they take.
+    pub fn set_shutdown_timeout(&mut self, timeout: Option<Duration>) {
+        self.shutdown_timeout = timeout;
+    }
+
+    /// Replaces the [`MimeTypes`] telling the type of served files.
+    pub fn set_mime_types(&mut self, mime_types: MimeTypes) {
+        *self.context.mime_types_mut() = mime_types;
     }
 
-    pub fn set_context(&mut self, ctx: Context) {
-        self.context = ctx;
+    /// Makes `state` available to handlers through [`Context::state`].
+    pub fn add_state<T: Send + Sync + 'static>(&mut self, state: T) {
+        self.context.insert_state(state);
+    }
+}
+
+impl Debug for HttpServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpServer")
+            .field("logging_enabled", &self.logging_enabled)
+            .field("router", &self.router)
+            .field("middlewares", &self.middlewares.len())
+            .field("context", &self.context)
+            .field("limits", &self.limits)
+            .field("timeouts", &self.timeouts)
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .field("listeners", &self.listeners)
+            .field("encodings", &self.encodings)
+            .field("compression", &self.compression)
+            .finish()
     }
 }
 
@@ -68,3 +342,28 @@ impl Logging for HttpServer {
         self.logging_enabled
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_workdir_keeps_states_and_mime_types() {
+        let mut server = HttpServer::new(HttpRouter::new());
+        server.add_state(42u32);
+        let mut mime_types = MimeTypes::new();
+        mime_types.insert("md", "text/markdown");
+        server.set_mime_types(mime_types);
+
+        server.set_workdir("/srv");
+        assert_eq!(server.context.workdir, "/srv");
+        assert_eq!(server.context.state::<u32>(), Some(&42));
+        assert_eq!(
+            server
+                .context
+                .mime_types()
+                .lookup(std::path::Path::new("notes.md")),
+            Some("text/markdown")
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::{future, sync::Arc};
+
+use tokio::sync::watch;
+
+/// Stops a running [`HttpServer`](super::server::HttpServer) from another task.
+///
+/// Once triggered the server stops accepting connections, lets the requests in
+/// flight finish and returns from `listen`.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self {
+            sender: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    pub(crate) fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            receiver: self.sender.subscribe(),
+        }
+    }
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The receiving side of a [`ShutdownHandle`], held by connections.
+#[derive(Debug, Clone)]
+pub(crate) struct ShutdownSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownSignal {
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once shutdown has been triggered.
+    pub async fn wait(&mut self) {
+        if self
+            .receiver
+            .wait_for(|triggered| *triggered)
+            .await
+            .is_err()
+        {
+            // the handle is gone, nothing can trigger a shutdown anymore
+            future::pending::<()>().await;
+        }
+    }
+}
+
+/// Resolves once `shutdown` is triggered, never when there is none.
+pub(crate) async fn wait_for_shutdown(shutdown: &mut Option<ShutdownSignal>) {
+    match shutdown {
+        Some(shutdown) => shutdown.wait().await,
+        None => future::pending().await,
+    }
+}
+
+/// Resolves when the process is asked to terminate, with Ctrl-C or SIGTERM.
+pub(crate) async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("cannot listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use tokio::fs;
+
+use crate::{
+    core::{
+        encoding::negotiate_coding,
+        endpoint::{BoxFuture, Handler},
+        files::{FileResolver, ResolveError},
+        mime::MimeTypes,
+        server::Context,
+        url::percent_encode_segment,
+    },
+    types::{
+        body::Body, headers::parse_weighted, request::HttpRequest, response::HttpResponse,
+        status::StatusCode,
+    },
+};
+
+/// Serves the files of a directory, mounted at a prefix with
+/// [`HttpRouter::static_files`](super::router::HttpRouter::static_files).
+///
+/// Request paths below the prefix map to files below the root, which cannot be
+/// escaped (see [`FileResolver`]). Directories are answered with their index file
+/// when there is one, otherwise with a listing of their entries if listings are
+/// enabled. Paths matching nothing can be answered with a fallback file, which lets
+/// single-page applications handle their own routes.
+///
+/// File types come from the [`MimeTypes`] of the [`Context`] and precompressed
+/// `.br`/`.zst`/`.gz` siblings are sent to clients accepting them.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_file: Option<String>,
+    listing: bool,
+    fallback: Option<String>,
+}
+
+impl StaticFiles {
+    /// Serves the files below `root`, with `index.html` as index file, no listings
+    /// and no fallback.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index_file: Some("index.html".to_string()),
+            listing: false,
+            fallback: None,
+        }
+    }
+
+    /// Sets the file served for directories, `None` to never serve one.
+    pub fn index_file(&mut self, name: Option<&str>) -> &mut Self {
+        self.index_file = name.map(String::from);
+        self
+    }
+
+    /// Lists the entries of directories without index file, as HTML or as JSON for
+    /// clients accepting `application/json`. Hidden entries (`.name`) are left out.
+    pub fn listing(&mut self, enabled: bool) -> &mut Self {
+        self.listing = enabled;
+        self
+    }
+
+    /// Serves `path`, relative to the root, for paths matching no file, e.g.
+    /// `index.html` for a single-page application. `None` answers them with
+    /// `404 Not Found`.
+    pub fn fallback(&mut self, path: Option<&str>) -> &mut Self {
+        self.fallback = path.map(String::from);
+        self
+    }
+
+    async fn respond(&self, req: HttpRequest, ctx: Context) -> HttpResponse {
+        let relative = req.path_params.get("path").map_or("", String::as_str);
+        let resolver = match FileResolver::new(&self.root).await {
+            Ok(resolver) => resolver,
+            Err(err) => return status(err.status_code()),
+        };
+        let path = match resolver.resolve(relative).await {
+            Ok(path) => path,
+            Err(ResolveError::NotFound) => {
+                return self.not_found(&req, &ctx, &resolver).await;
+            }
+            Err(err) => return status(err.status_code()),
+        };
+        let is_dir = match fs::metadata(&path).await {
+            Ok(metadata) => metadata.is_dir(),
+            Err(_) => return self.not_found(&req, &ctx, &resolver).await,
+        };
+        if !is_dir {
+            return serve_file(&req, ctx.mime_types(), &resolver, relative, &path).await;
+        }
+
+        // relative links in the index or listing only resolve inside the directory
+        // when its URL ends with a slash
+        let (raw_path, query) = req.target.split_once('?').unwrap_or((&req.target, ""));
+        if !raw_path.ends_with('/') {
+            let mut location = format!("{}/", raw_path);
+            if !query.is_empty() {
+                location.push('?');
+                location.push_str(query);
+            }
+            return HttpResponse::builder()
+                .status_code(StatusCode::MovedPermanently)
+                .header("Location", &location)
+                .build();
+        }
+
+        if let Some(index_file) = &self.index_file {
+            let index_relative = format!("{}/{}", relative, index_file);
+            if let Ok(index_path) = resolver.resolve(&index_relative).await
+                && is_file(&index_path).await
+            {
+                return serve_file(
+                    &req,
+                    ctx.mime_types(),
+                    &resolver,
+                    &index_relative,
+                    &index_path,
+                )
+                .await;
+            }
+        }
+        if self.listing {
+            return match list_directory(&path).await {
+                Ok(entries) => listing_response(&req, relative, entries),
+                Err(_) => status(StatusCode::InternalServerError),
+            };
+        }
+        self.not_found(&req, &ctx, &resolver).await
+    }
+
+    async fn not_found(
+        &self,
+        req: &HttpRequest,
+        ctx: &Context,
+        resolver: &FileResolver,
+    ) -> HttpResponse {
+        if let Some(fallback) = &self.fallback
+            && let Ok(path) = resolver.resolve(fallback).await
+            && is_file(&path).await
+        {
+            return serve_file(req, ctx.mime_types(), resolver, fallback, &path).await;
+        }
+        status(StatusCode::NotFound)
+    }
+}
+
+impl Handler for StaticFiles {
+    fn call(&self, req: HttpRequest, ctx: Context) -> BoxFuture<HttpResponse> {
+        let files = self.clone();
+        Box::pin(async move { files.respond(req, ctx).await })
+    }
+}
+
+/// Sends the file at `path`, which `relative` resolved to. The file is streamed rather
+/// than loaded in memory, and a precompressed sibling is sent instead when the client
+/// accepts its coding.
+pub async fn serve_file(
+    req: &HttpRequest,
+    mime_types: &MimeTypes,
+    resolver: &FileResolver,
+    relative: &str,
+    path: &Path,
+) -> HttpResponse {
+    let variants = if is_file(path).await {
+        resolver.precompressed(relative).await
+    } else {
+        Vec::new()
+    };
+    let accept_encoding = req.headers.get_all("Accept-Encoding");
+    let coding = negotiate_coding(accept_encoding, variants.iter().map(|(coding, _)| *coding));
+    let (sent_path, coding) = match variants.iter().find(|(name, _)| Some(*name) == coding) {
+        Some((coding, variant)) => (variant.as_path(), Some(*coding)),
+        None => (path, None),
+    };
+
+    let Ok(file) = fs::File::open(sent_path).await else {
+        return status(StatusCode::NotFound);
+    };
+    let Ok(metadata) = file.metadata().await else {
+        return status(StatusCode::NotFound);
+    };
+    if !metadata.is_file() {
+        return status(StatusCode::NotFound);
+    }
+
+    // the type is the one of the original file, also for precompressed variants
+    let content_type = mime_types.content_type(path).await;
+    let mut response = HttpResponse::builder();
+    response
+        .status_code(StatusCode::Ok)
+        .file(Body::from_reader(file, Some(metadata.len())))
+        .header("Content-Type", &content_type);
+    if !variants.is_empty() {
+        response.header("Vary", "Accept-Encoding");
+    }
+    if let Some(coding) = coding {
+        response.header("Content-Encoding", coding);
+    }
+    response.build()
+}
+
+async fn is_file(path: &Path) -> bool {
+    fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file())
+}
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    /// Seconds since the Unix epoch
+    modified: Option<u64>,
+}
+
+/// Reads the visible entries of `dir`, directories first, then by name.
+async fn list_directory(dir: &Path) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        // follows symlinks, so that they are listed as what they point to
+        let Ok(metadata) = fs::metadata(entry.path()).await else {
+            continue;
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|elapsed| elapsed.as_secs());
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified,
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+/// Lists `entries` of the directory at `relative` below the root.
+fn listing_response(req: &HttpRequest, relative: &str, entries: Vec<Entry>) -> HttpResponse {
+    let dir = format!("{}/", req.path.trim_end_matches('/'));
+    let has_parent = !relative.trim_matches('/').is_empty();
+    let wants_json = prefers_json(req.headers.get_all("Accept"));
+    let mut response = HttpResponse::builder();
+    response
+        .status_code(StatusCode::Ok)
+        .header("Vary", "Accept");
+    if wants_json {
+        response.json(render_json(&dir, &entries));
+    } else {
+        response
+            .body(render_html(&dir, has_parent, &entries))
+            .header("Content-Type", "text/html; charset=utf-8");
+    }
+    response.build()
+}
+
+/// Whether the `Accept` field values of a request rank `application/json` above
+/// `text/html`, following their q-values. HTML wins ties, e.g. `*/*`.
+fn prefers_json<'a>(accept: impl Iterator<Item = &'a str>) -> bool {
+    let ranges: Vec<(String, f32)> = accept
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_media_range)
+        .collect();
+    let json = media_type_weight(&ranges, "application/json");
+    json > 0.0 && json > media_type_weight(&ranges, "text/html")
+}
+
+/// The q-value given to `media_type` by the most specific matching range.
+fn media_type_weight(ranges: &[(String, f32)], media_type: &str) -> f32 {
+    let top_level = media_type.split('/').next().unwrap_or("");
+    let specificity = |range: &str| match range.split_once('/') {
+        _ if range == media_type => Some(2),
+        Some((range_top, "*")) if range_top == top_level => Some(1),
+        Some(("*", "*")) => Some(0),
+        _ => None,
+    };
+    ranges
+        .iter()
+        .filter_map(|(range, q)| specificity(range).map(|specificity| (specificity, *q)))
+        .max_by_key(|(specificity, _)| *specificity)
+        .map_or(0.0, |(_, q)| q)
+}
+
+/// Parses `type/subtype[;params]`, ignoring ranges with an invalid weight.
+fn parse_media_range(item: &str) -> Option<(String, f32)> {
+    parse_weighted(item).filter(|(range, _)| range.contains('/'))
+}
+
+fn render_html(dir: &str, has_parent: bool, entries: &[Entry]) -> String {
+    let title = format!("Index of {}", escape_html(dir));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+         <body>\n<h1>{0}</h1>\n<table>\n\
+         <tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+        title
+    );
+    if has_parent {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            entry.size.to_string()
+        };
+        let modified = entry.modified.map(format_timestamp).unwrap_or_default();
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}{slash}\">{}{slash}</a></td><td>{}</td><td>{}</td></tr>",
+            percent_encode_segment(&entry.name),
+            escape_html(&entry.name),
+            size,
+            modified,
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn render_json(dir: &str, entries: &[Entry]) -> String {
+    let mut json = format!("{{\"path\":{},\"entries\":[", json_string(dir));
+    for (index, entry) in entries.iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        let modified = entry
+            .modified
+            .map_or("null".to_string(), |secs| secs.to_string());
+        let _ = write!(
+            json,
+            "{{\"name\":{},\"type\":\"{}\",\"size\":{},\"modified\":{}}}",
+            json_string(&entry.name),
+            if entry.is_dir { "directory" } else { "file" },
+            entry.size,
+            modified,
+        );
+    }
+    json.push_str("]}");
+    json
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM UTC`.
+fn format_timestamp(secs: u64) -> String {
+    // days to civil date, from Howard Hinnant's `civil_from_days`
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    let seconds_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn status(status: StatusCode) -> HttpResponse {
+    HttpResponse::builder().status_code(status).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing_format_follows_accept_weights() {
+        let prefers = |accept: &str| prefers_json(std::iter::once(accept));
+        assert!(prefers("application/json"));
+        assert!(prefers("text/html;q=0.5, application/json"));
+        assert!(prefers("application/*"));
+        assert!(!prefers("application/json;q=0"));
+        assert!(!prefers("application/json;q=0, */*"));
+        assert!(!prefers("*/*"));
+        assert!(!prefers("text/html, application/json;q=0.9"));
+        assert!(!prefers_json(std::iter::empty()));
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use rustls::{
+    ServerConfig,
+    crypto::{CryptoProvider, ring},
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use thiserror::Error;
+use tokio_rustls::TlsAcceptor;
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("cannot read {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("invalid PEM in {path}: {source}")]
+    Pem {
+        path: PathBuf,
+        source: rustls::pki_types::pem::Error,
+    },
+    #[error("no certificate found in {0}")]
+    NoCertificate(PathBuf),
+    #[error(transparent)]
+    Rustls(#[from] rustls::Error),
+}
+
+/// Certificates served by the TLS listeners of an [`HttpServer`](super::server::HttpServer).
+///
+/// A default certificate is used for every connection unless a certificate was added
+/// for the server name the client asked for (SNI). Certificates are read from PEM
+/// files and can be read again with [`TlsConfig::reload`], e.g. after a renewal;
+/// connections made afterwards use the new certificates. Clones share the same
+/// certificates.
+#[derive(Clone)]
+pub struct TlsConfig {
+    resolver: Arc<CertResolver>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl TlsConfig {
+    /// Reads the default certificate chain and its private key from PEM files.
+    pub fn from_pem_files(
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self, TlsError> {
+        let provider = Arc::new(ring::default_provider());
+        let source = PemSource {
+            cert_path: cert_path.as_ref().to_path_buf(),
+            key_path: key_path.as_ref().to_path_buf(),
+        };
+        let default = source.load(&provider)?;
+
+        let resolver = CertResolver {
+            sources: RwLock::new(Sources {
+                default: source,
+                by_name: HashMap::new(),
+            }),
+            certificates: RwLock::new(Certificates {
+                default,
+                by_name: HashMap::new(),
+            }),
+        };
+        Ok(Self {
+            resolver: Arc::new(resolver),
+            provider,
+        })
+    }
+
+    /// Serves the certificate read from `cert_path` and `key_path` to clients asking
+    /// for `server_name`.
+    pub fn add_server_name(
+        &self,
+        server_name: &str,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<(), TlsError> {
+        let server_name = server_name.to_ascii_lowercase();
+        let source = PemSource {
+            cert_path: cert_path.as_ref().to_path_buf(),
+            key_path: key_path.as_ref().to_path_buf(),
+        };
+        let certificate = source.load(&self.provider)?;
+
+        self.resolver
+            .sources
+            .write()
+            .unwrap()
+            .by_name
+            .insert(server_name.clone(), source);
+        self.resolver
+            .certificates
+            .write()
+            .unwrap()
+            .by_name
+            .insert(server_name, certificate);
+        Ok(())
+    }
+
+    /// Reads every certificate again from its files. Nothing changes if any of them
+    /// cannot be loaded.
+    pub fn reload(&self) -> Result<(), TlsError> {
+        // held until the certificates are replaced, so that a server name added in
+        // the meantime is not dropped
+        let sources = self.resolver.sources.read().unwrap();
+        let mut by_name = HashMap::new();
+        for (name, source) in &sources.by_name {
+            by_name.insert(name.clone(), source.load(&self.provider)?);
+        }
+        let reloaded = Certificates {
+            default: sources.default.load(&self.provider)?,
+            by_name,
+        };
+        *self.resolver.certificates.write().unwrap() = reloaded;
+        Ok(())
+    }
+
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor, TlsError> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(self.resolver.clone());
+        // HTTP/1.1 is the only protocol spoken here
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("resolver", &self.resolver)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct PemSource {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+}
+
+impl PemSource {
+    fn load(&self, provider: &CryptoProvider) -> Result<Arc<CertifiedKey>, TlsError> {
+        let pem_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source| match source {
+                rustls::pki_types::pem::Error::Io(source) => TlsError::Io { path, source },
+                source => TlsError::Pem { path, source },
+            }
+        };
+
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .map_err(pem_error(&self.cert_path))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(pem_error(&self.cert_path))?;
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificate(self.cert_path.clone()));
+        }
+        let key =
+            PrivateKeyDer::from_pem_file(&self.key_path).map_err(pem_error(&self.key_path))?;
+
+        Ok(Arc::new(CertifiedKey::from_der(certs, key, provider)?))
+    }
+}
+
+#[derive(Debug)]
+struct Sources {
+    default: PemSource,
+    by_name: HashMap<String, PemSource>,
+}
+
+struct Certificates {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+/// Picks the certificate for a handshake from the SNI server name.
+struct CertResolver {
+    sources: RwLock<Sources>,
+    certificates: RwLock<Certificates>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certificates = self.certificates.read().unwrap();
+        let by_name = client_hello
+            .server_name()
+            .and_then(|name| certificates.by_name.get(&name.to_ascii_lowercase()));
+        Some(by_name.unwrap_or(&certificates.default).clone())
+    }
+}
+
+impl fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertResolver")
+            .field("sources", &self.sources)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustls::{
+        ClientConfig, DigitallySignedStruct, SignatureScheme,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        pki_types::{ServerName, UnixTime},
+    };
+    use tokio_rustls::TlsConnector;
+
+    /// Accepts any certificate, the tests look at which one was presented.
+    #[derive(Debug)]
+    struct AcceptAny;
+
+    impl ServerCertVerifier for AcceptAny {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            ring::default_provider()
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+
+    /// Writes a new self-signed certificate for `name` to `dir`, returning the paths
+    /// of the certificate and key files and the certificate itself.
+    fn self_signed(dir: &Path, name: &str) -> (PathBuf, PathBuf, Vec<u8>) {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let cert_path = dir.join(format!("{name}.crt"));
+        let key_path = dir.join(format!("{name}.key"));
+        std::fs::write(&cert_path, generated.cert.pem()).unwrap();
+        std::fs::write(&key_path, generated.signing_key.serialize_pem()).unwrap();
+        (cert_path, key_path, generated.cert.der().to_vec())
+    }
+
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tls-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Runs a handshake asking for `server_name`, returning the certificate the
+    /// server presented and the negotiated ALPN protocol.
+    async fn handshake(config: &TlsConfig, server_name: &str) -> (Vec<u8>, Option<Vec<u8>>) {
+        let mut client = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAny))
+            .with_no_client_auth();
+        client.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let connector = TlsConnector::from(Arc::new(client));
+        let acceptor = config.acceptor().unwrap();
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let (client, server) = tokio::join!(
+            connector.connect(server_name, client_io),
+            acceptor.accept(server_io)
+        );
+        server.unwrap();
+        let client = client.unwrap();
+        let (_, connection) = client.get_ref();
+        let presented = connection.peer_certificates().unwrap()[0].to_vec();
+        (presented, connection.alpn_protocol().map(<[u8]>::to_vec))
+    }
+
+    #[tokio::test]
+    async fn selects_certificate_by_server_name() {
+        let dir = temp_dir("sni");
+        let (cert, key, default_der) = self_signed(&dir, "default.test");
+        let (other_cert, other_key, other_der) = self_signed(&dir, "other.test");
+        let config = TlsConfig::from_pem_files(&cert, &key).unwrap();
+        config
+            .add_server_name("Other.Test", &other_cert, &other_key)
+            .unwrap();
+
+        let (presented, alpn) = handshake(&config, "other.test").await;
+        assert_eq!(presented, other_der);
+        assert_eq!(alpn.as_deref(), Some(&b"http/1.1"[..]));
+
+        let (presented, _) = handshake(&config, "unknown.test").await;
+        assert_eq!(presented, default_der);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_swaps_certificates() {
+        let dir = temp_dir("reload");
+        let (cert, key, old_der) = self_signed(&dir, "default.test");
+        let config = TlsConfig::from_pem_files(&cert, &key).unwrap();
+        assert_eq!(handshake(&config, "default.test").await.0, old_der);
+
+        // a renewal writes new files in place
+        let (_, _, new_der) = self_signed(&dir, "default.test");
+        assert_eq!(handshake(&config, "default.test").await.0, old_der);
+        config.reload().unwrap();
+        assert_eq!(handshake(&config, "default.test").await.0, new_der);
+
+        // a broken file leaves the loaded certificates in place
+        std::fs::write(&key, "not a key").unwrap();
+        assert!(config.reload().is_err());
+        assert_eq!(handshake(&config, "default.test").await.0, new_der);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
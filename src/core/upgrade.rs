@@ -0,0 +1,49 @@
+use std::{fmt, future::Future, time::Duration};
+
+use bytes::BytesMut;
+
+use crate::core::endpoint::BoxFuture;
+use crate::core::listener::BoxConnection;
+use crate::core::shutdown::ShutdownSignal;
+
+/// A connection taken over from HTTP after a `101 Switching Protocols` response.
+pub struct Upgraded {
+    pub io: BoxConnection,
+    /// Bytes the client sent after its upgrade request, already read from `io`
+    pub read_buf: BytesMut,
+    /// Triggered when the server shuts down
+    pub(crate) shutdown: Option<ShutdownSignal>,
+    /// The idle timeout of the server, which protocols may keep applying
+    pub(crate) idle_timeout: Option<Duration>,
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("read_buf", &self.read_buf.len())
+            .finish()
+    }
+}
+
+/// What runs on the connection once an upgrade response has been written.
+pub struct OnUpgrade(Box<dyn FnOnce(Upgraded) -> BoxFuture<()> + Send>);
+
+impl OnUpgrade {
+    pub fn new<F, Fut>(on_upgrade: F) -> Self
+    where
+        F: FnOnce(Upgraded) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self(Box::new(move |upgraded| Box::pin(on_upgrade(upgraded))))
+    }
+
+    pub async fn run(self, upgraded: Upgraded) {
+        (self.0)(upgraded).await
+    }
+}
+
+impl fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnUpgrade")
+    }
+}
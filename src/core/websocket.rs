@@ -0,0 +1,714 @@
+use std::{future::Future, io, sync::Arc, time::Duration};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::{Buf, Bytes, BytesMut};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::timeout,
+};
+
+use crate::core::endpoint::{BoxFuture, Handler};
+use crate::core::listener::BoxConnection;
+use crate::core::server::Context;
+use crate::core::shutdown::{ShutdownSignal, wait_for_shutdown};
+use crate::core::upgrade::Upgraded;
+use crate::types::request::HttpRequest;
+use crate::types::response::HttpResponse;
+use crate::types::status::StatusCode;
+
+// appended to the client key to compute Sec-WebSocket-Accept (RFC 6455, section 1.3)
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Something that can serve a WebSocket connection, once the opening handshake
+/// succeeded. It receives the upgrade request along with the socket.
+///
+/// It is implemented for closures `Fn(WebSocket, HttpRequest, Context) -> impl Future<Output = ()>`.
+pub trait WebSocketHandler: Send + Sync + 'static {
+    fn call(&self, socket: WebSocket, req: HttpRequest, ctx: Context) -> BoxFuture<()>;
+}
+
+impl<F, Fut> WebSocketHandler for F
+where
+    F: Fn(WebSocket, HttpRequest, Context) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn call(&self, socket: WebSocket, req: HttpRequest, ctx: Context) -> BoxFuture<()> {
+        Box::pin(self(socket, req, ctx))
+    }
+}
+
+/// The `GET` handler of a WebSocket route: validates the opening handshake and
+/// answers `101 Switching Protocols`, then runs the WebSocket handler.
+pub(crate) struct UpgradeHandler {
+    handler: Arc<dyn WebSocketHandler>,
+}
+
+impl UpgradeHandler {
+    pub fn new(handler: impl WebSocketHandler) -> Self {
+        Self {
+            handler: Arc::new(handler),
+        }
+    }
+}
+
+impl Handler for UpgradeHandler {
+    fn call(&self, req: HttpRequest, ctx: Context) -> BoxFuture<HttpResponse> {
+        let handler = self.handler.clone();
+        Box::pin(async move {
+            let accept = match handshake_accept(&req) {
+                Ok(accept) => accept,
+                Err(StatusCode::UpgradeRequired) => {
+                    return HttpResponse::builder()
+                        .status_code(StatusCode::UpgradeRequired)
+                        .header("Upgrade", "websocket")
+                        .header("Sec-WebSocket-Version", "13")
+                        .build();
+                }
+                Err(status) => return HttpResponse::builder().status_code(status).build(),
+            };
+
+            HttpResponse::builder()
+                .status_code(StatusCode::SwitchingProtocols)
+                .header("Upgrade", "websocket")
+                .header("Connection", "Upgrade")
+                .header("Sec-WebSocket-Accept", &accept)
+                .on_upgrade(move |upgraded| async move {
+                    handler.call(WebSocket::new(upgraded), req, ctx).await;
+                })
+                .build()
+        })
+    }
+}
+
+/// Checks the opening handshake of `req` and computes its `Sec-WebSocket-Accept`.
+fn handshake_accept(req: &HttpRequest) -> Result<String, StatusCode> {
+    let headers = &req.headers;
+    if !headers.has_token("Upgrade", "websocket") || !headers.has_token("Connection", "upgrade") {
+        return Err(StatusCode::UpgradeRequired);
+    }
+    if req.version != "HTTP/1.1" {
+        return Err(StatusCode::BadRequest);
+    }
+    if headers.get("Sec-WebSocket-Version") != Some("13") {
+        return Err(StatusCode::UpgradeRequired);
+    }
+
+    let key = headers
+        .get("Sec-WebSocket-Key")
+        .ok_or(StatusCode::BadRequest)?;
+    // the key is 16 random bytes, base64 encoded
+    match STANDARD.decode(key) {
+        Ok(nonce) if nonce.len() == 16 => {}
+        _ => return Err(StatusCode::BadRequest),
+    }
+
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(HANDSHAKE_GUID.as_bytes());
+    Ok(STANDARD.encode(sha1.finalize()))
+}
+
+#[derive(Debug, Error)]
+pub enum WebSocketError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("websocket protocol violation: {0}")]
+    Protocol(&'static str),
+    #[error("text message is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("message is larger than the limit")]
+    MessageTooBig,
+    #[error("websocket is closed")]
+    Closed,
+}
+
+impl WebSocketError {
+    /// The status code of the close frame sent to the client because of this error.
+    fn close_code(&self) -> Option<u16> {
+        match self {
+            WebSocketError::Protocol(_) => Some(1002),
+            WebSocketError::InvalidUtf8 => Some(1007),
+            WebSocketError::MessageTooBig => Some(1009),
+            WebSocketError::Io(_) | WebSocketError::Closed => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    Close(Option<CloseFrame>),
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Bytes,
+}
+
+/// A WebSocket connection, exchanging whole messages.
+///
+/// Fragmented messages are reassembled before [`WebSocket::recv`] returns them,
+/// pings are answered with pongs and a close frame from the client is echoed, all
+/// while still returning those control messages. Protocol violations close the
+/// connection with the matching status code.
+///
+/// The connection is closed with status 1001 (going away) when the server shuts down,
+/// or when nothing was received for the idle timeout of the server.
+pub struct WebSocket {
+    io: BoxConnection,
+    read_buf: BytesMut,
+    shutdown: Option<ShutdownSignal>,
+    idle_timeout: Option<Duration>,
+    max_message_size: usize,
+    // opcode and data of a fragmented message still being received
+    fragments: Option<(u8, BytesMut)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    pub fn new(upgraded: Upgraded) -> Self {
+        Self {
+            io: upgraded.io,
+            read_buf: upgraded.read_buf,
+            shutdown: upgraded.shutdown,
+            idle_timeout: upgraded.idle_timeout,
+            max_message_size: MAX_MESSAGE_SIZE,
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Limits the size of received messages, after reassembly. Larger messages close
+    /// the connection with status 1009.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Sets how long the client may stay silent before the connection is closed, `None`
+    /// to wait forever. Defaults to the idle timeout of the server.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Receives the next message. Returns `None` once the closing handshake is done.
+    pub async fn recv(&mut self) -> Option<Result<Message, WebSocketError>> {
+        if self.close_received {
+            return None;
+        }
+        match self.read_message().await {
+            Ok(message) => Some(Ok(message)),
+            Err(err) => {
+                if let Some(code) = err.close_code() {
+                    let _ = self.close(code, "").await;
+                }
+                // nothing more can be read after a violation
+                self.close_received = true;
+                Some(Err(err))
+            }
+        }
+    }
+
+    /// Sends a message. Close messages start the closing handshake, see [`WebSocket::close`].
+    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        match message {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(OP_BINARY, &data).await,
+            Message::Ping(data) => self.write_frame(OP_PING, &data).await,
+            Message::Pong(data) => self.write_frame(OP_PONG, &data).await,
+            Message::Close(Some(frame)) => self.close(frame.code, &frame.reason).await,
+            Message::Close(None) => self.close(1000, "").await,
+        }
+    }
+
+    /// Starts the closing handshake. Keep calling [`WebSocket::recv`] until it returns
+    /// `None` to let the client answer.
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        let mut payload = Vec::with_capacity(2 + reason.len());
+        payload.extend_from_slice(&code.to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+        self.write_frame(OP_CLOSE, &payload).await?;
+        self.close_sent = true;
+        Ok(())
+    }
+
+    async fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            let Some(frame) = self.parse_frame()? else {
+                self.fill_buffer().await?;
+                continue;
+            };
+            if let Some(message) = self.handle_frame(frame).await? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Reads more bytes from the client. Once the server shuts down or the client idled
+    /// out, the closing handshake is started and the client gets one more idle
+    /// timeout to answer it.
+    async fn fill_buffer(&mut self) -> Result<(), WebSocketError> {
+        let read = async {
+            match self.idle_timeout {
+                Some(idle_timeout) => timeout(idle_timeout, self.io.read_buf(&mut self.read_buf))
+                    .await
+                    .ok(),
+                None => Some(self.io.read_buf(&mut self.read_buf).await),
+            }
+        };
+        let read = tokio::select! {
+            read = read => read,
+            _ = wait_for_shutdown(&mut self.shutdown), if !self.close_sent => None,
+        };
+
+        match read {
+            Some(Ok(0)) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed without a close frame",
+            )
+            .into()),
+            Some(Ok(_)) => Ok(()),
+            Some(Err(err)) => Err(err.into()),
+            None if !self.close_sent => self.close(1001, "going away").await,
+            None => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Client did not answer the close frame",
+            )
+            .into()),
+        }
+    }
+
+    /// Takes the next complete frame out of the read buffer.
+    fn parse_frame(&mut self) -> Result<Option<Frame>, WebSocketError> {
+        let buf = &self.read_buf;
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let fin = buf[0] & 0x80 != 0;
+        if buf[0] & 0x70 != 0 {
+            return Err(WebSocketError::Protocol(
+                "reserved bits set without extension",
+            ));
+        }
+        let opcode = buf[0] & 0x0F;
+        if buf[1] & 0x80 == 0 {
+            return Err(WebSocketError::Protocol("client frames must be masked"));
+        }
+
+        let (length, mut header_len) = match buf[1] & 0x7F {
+            126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+            127 if buf.len() >= 10 => (u64::from_be_bytes(buf[2..10].try_into().unwrap()), 10),
+            126 | 127 => return Ok(None),
+            length => (length as u64, 2),
+        };
+        if opcode >= OP_CLOSE && (!fin || length > 125) {
+            return Err(WebSocketError::Protocol("invalid control frame"));
+        }
+        if length > self.max_message_size as u64 {
+            return Err(WebSocketError::MessageTooBig);
+        }
+        let length = length as usize;
+
+        let mask: [u8; 4] = match buf.get(header_len..header_len + 4) {
+            Some(mask) => mask.try_into().unwrap(),
+            None => return Ok(None),
+        };
+        header_len += 4;
+        if buf.len() < header_len + length {
+            return Ok(None);
+        }
+
+        self.read_buf.advance(header_len);
+        let mut payload = self.read_buf.split_to(length);
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload: payload.freeze(),
+        }))
+    }
+
+    /// Returns the message completed by `frame`, if any.
+    async fn handle_frame(&mut self, frame: Frame) -> Result<Option<Message>, WebSocketError> {
+        match frame.opcode {
+            OP_CONTINUATION => {
+                let Some((_, data)) = self.fragments.as_mut() else {
+                    return Err(WebSocketError::Protocol("continuation without a message"));
+                };
+                if data.len() + frame.payload.len() > self.max_message_size {
+                    return Err(WebSocketError::MessageTooBig);
+                }
+                data.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    return Ok(None);
+                }
+                let (opcode, data) = self.fragments.take().unwrap();
+                data_message(opcode, data.freeze()).map(Some)
+            }
+            OP_TEXT | OP_BINARY => {
+                if self.fragments.is_some() {
+                    return Err(WebSocketError::Protocol(
+                        "new message before the last one ended",
+                    ));
+                }
+                if !frame.fin {
+                    self.fragments = Some((frame.opcode, BytesMut::from(&frame.payload[..])));
+                    return Ok(None);
+                }
+                data_message(frame.opcode, frame.payload).map(Some)
+            }
+            OP_PING => {
+                if !self.close_sent {
+                    self.write_frame(OP_PONG, &frame.payload).await?;
+                }
+                Ok(Some(Message::Ping(frame.payload)))
+            }
+            OP_PONG => Ok(Some(Message::Pong(frame.payload))),
+            OP_CLOSE => {
+                let close = parse_close(&frame.payload)?;
+                self.close_received = true;
+                if !self.close_sent {
+                    let code = close.as_ref().map_or(1000, |close| close.code);
+                    self.close(code, "").await?;
+                }
+                self.io.shutdown().await?;
+                Ok(Some(Message::Close(close)))
+            }
+            _ => Err(WebSocketError::Protocol("unknown opcode")),
+        }
+    }
+
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        // server frames are never masked
+        let mut header = Vec::with_capacity(10);
+        header.push(0x80 | opcode);
+        match payload.len() {
+            length @ 0..=125 => header.push(length as u8),
+            length @ 126..=0xFFFF => {
+                header.push(126);
+                header.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                header.push(127);
+                header.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        self.io.write_all(&header).await?;
+        self.io.write_all(payload).await?;
+        self.io.flush().await?;
+        Ok(())
+    }
+}
+
+fn data_message(opcode: u8, data: Bytes) -> Result<Message, WebSocketError> {
+    if opcode == OP_TEXT {
+        let text = String::from_utf8(data.into()).map_err(|_| WebSocketError::InvalidUtf8)?;
+        Ok(Message::Text(text))
+    } else {
+        Ok(Message::Binary(data))
+    }
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, WebSocketError> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(WebSocketError::Protocol("truncated close code")),
+        [high, low, reason @ ..] => {
+            let code = u16::from_be_bytes([*high, *low]);
+            if !matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999) {
+                return Err(WebSocketError::Protocol("invalid close code"));
+            }
+            let reason = std::str::from_utf8(reason).map_err(|_| WebSocketError::InvalidUtf8)?;
+            Ok(Some(CloseFrame {
+                code,
+                reason: reason.to_string(),
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::{DuplexStream, duplex};
+
+    use crate::core::parser::Parser;
+    use crate::core::shutdown::ShutdownHandle;
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    fn socket(
+        shutdown: Option<ShutdownSignal>,
+        idle_timeout: Option<Duration>,
+    ) -> (WebSocket, DuplexStream) {
+        let (client, server) = duplex(1 << 20);
+        let upgraded = Upgraded {
+            io: Box::new(server),
+            read_buf: BytesMut::new(),
+            shutdown,
+            idle_timeout,
+        };
+        (WebSocket::new(upgraded), client)
+    }
+
+    /// A frame as a client sends it, masked.
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            length @ 0..=125 => frame.push(0x80 | length as u8),
+            length @ 126..=0xFFFF => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&MASK);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+        frame
+    }
+
+    fn close_payload(code: u16, reason: &[u8]) -> Vec<u8> {
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason);
+        payload
+    }
+
+    /// Reads a frame sent by the server, returning its opcode and payload.
+    async fn server_frame(client: &mut DuplexStream) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        client.read_exact(&mut head).await.unwrap();
+        assert_eq!(head[0] & 0xF0, 0x80, "server frames are final");
+        assert_eq!(head[1] & 0x80, 0, "server frames are not masked");
+        let length = match head[1] {
+            126 => client.read_u16().await.unwrap() as usize,
+            127 => client.read_u64().await.unwrap() as usize,
+            length => length as usize,
+        };
+        let mut payload = vec![0; length];
+        client.read_exact(&mut payload).await.unwrap();
+        (head[0] & 0x0F, payload)
+    }
+
+    /// Sends `data` from the client and checks the server closes with `code`.
+    async fn assert_rejected(data: &[u8], code: u16) -> WebSocketError {
+        let (mut ws, mut client) = socket(None, None);
+        client.write_all(data).await.unwrap();
+
+        let err = ws.recv().await.unwrap().unwrap_err();
+        assert_eq!(
+            server_frame(&mut client).await,
+            (OP_CLOSE, close_payload(code, b""))
+        );
+        assert!(ws.recv().await.is_none());
+        err
+    }
+
+    async fn upgrade_request(head: &str) -> HttpRequest {
+        let raw = format!("{head}\r\n\r\n");
+        Parser::new()
+            .parse_http_request(&mut raw.as_bytes())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn handshake_accept_matches_rfc_sample() {
+        let handshake = "GET /chat HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\n\
+                         Connection: keep-alive, Upgrade\r\nSec-WebSocket-Version: 13";
+        let req = upgrade_request(&format!(
+            "{handshake}\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ=="
+        ))
+        .await;
+        assert_eq!(
+            handshake_accept(&req).unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let req = upgrade_request(&format!("{handshake}\r\nSec-WebSocket-Key: c2hvcnQ=")).await;
+        assert_eq!(handshake_accept(&req), Err(StatusCode::BadRequest));
+        let req = upgrade_request(handshake).await;
+        assert_eq!(handshake_accept(&req), Err(StatusCode::BadRequest));
+        let req = upgrade_request("GET /chat HTTP/1.1\r\nSec-WebSocket-Version: 13").await;
+        assert_eq!(handshake_accept(&req), Err(StatusCode::UpgradeRequired));
+    }
+
+    #[tokio::test]
+    async fn receives_every_length_encoding() {
+        let (mut ws, mut client) = socket(None, None);
+        // 7-bit, the largest 7-bit, 16-bit and 64-bit lengths
+        for length in [5, 125, 126, 0xFFFF, 0x10000] {
+            let payload: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            client
+                .write_all(&client_frame(true, OP_BINARY, &payload))
+                .await
+                .unwrap();
+            let message = ws.recv().await.unwrap().unwrap();
+            assert_eq!(message, Message::Binary(payload.into()), "length {length}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unmasked_frames() {
+        let err = assert_rejected(&[0x81, 0x02, b'h', b'i'], 1002).await;
+        assert!(matches!(err, WebSocketError::Protocol(_)));
+    }
+
+    #[tokio::test]
+    async fn rejects_reserved_bits() {
+        let mut frame = client_frame(true, OP_TEXT, b"hi");
+        frame[0] |= 0x40;
+        let err = assert_rejected(&frame, 1002).await;
+        assert!(matches!(err, WebSocketError::Protocol(_)));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_control_frames() {
+        let err = assert_rejected(&client_frame(true, OP_PING, &[0; 126]), 1002).await;
+        assert!(matches!(err, WebSocketError::Protocol(_)));
+        let err = assert_rejected(&client_frame(false, OP_PING, b"ping"), 1002).await;
+        assert!(matches!(err, WebSocketError::Protocol(_)));
+    }
+
+    #[tokio::test]
+    async fn reassembles_fragments_around_ping() {
+        let (mut ws, mut client) = socket(None, None);
+        let mut data = client_frame(false, OP_TEXT, b"Hel");
+        data.extend(client_frame(false, OP_CONTINUATION, b"lo, "));
+        data.extend(client_frame(true, OP_PING, b"ping"));
+        data.extend(client_frame(true, OP_CONTINUATION, "wörld".as_bytes()));
+        client.write_all(&data).await.unwrap();
+
+        let message = ws.recv().await.unwrap().unwrap();
+        assert_eq!(message, Message::Ping(Bytes::from_static(b"ping")));
+        assert_eq!(server_frame(&mut client).await, (OP_PONG, b"ping".to_vec()));
+        let message = ws.recv().await.unwrap().unwrap();
+        assert_eq!(message, Message::Text("Hello, wörld".to_string()));
+    }
+
+    #[tokio::test]
+    async fn rejects_unexpected_continuation() {
+        let err = assert_rejected(&client_frame(true, OP_CONTINUATION, b"x"), 1002).await;
+        assert!(matches!(err, WebSocketError::Protocol(_)));
+
+        let mut data = client_frame(false, OP_TEXT, b"a");
+        data.extend(client_frame(true, OP_TEXT, b"b"));
+        let err = assert_rejected(&data, 1002).await;
+        assert!(matches!(err, WebSocketError::Protocol(_)));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_close_codes() {
+        for code in [999, 1004, 1005, 1006, 1015, 2999, 5000] {
+            let frame = client_frame(true, OP_CLOSE, &close_payload(code, b""));
+            let err = assert_rejected(&frame, 1002).await;
+            assert!(matches!(err, WebSocketError::Protocol(_)), "code {code}");
+        }
+        let err = assert_rejected(&client_frame(true, OP_CLOSE, &[0x03]), 1002).await;
+        assert!(matches!(err, WebSocketError::Protocol(_)));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_utf8() {
+        let err = assert_rejected(&client_frame(true, OP_TEXT, b"\xff\xfe"), 1007).await;
+        assert!(matches!(err, WebSocketError::InvalidUtf8));
+
+        // text is checked once reassembled, so a code point cut at the end is caught
+        let mut data = client_frame(false, OP_TEXT, b"\xc3");
+        data.extend(client_frame(true, OP_CONTINUATION, b""));
+        let err = assert_rejected(&data, 1007).await;
+        assert!(matches!(err, WebSocketError::InvalidUtf8));
+
+        let frame = client_frame(true, OP_CLOSE, &close_payload(1000, b"\xc0"));
+        let err = assert_rejected(&frame, 1007).await;
+        assert!(matches!(err, WebSocketError::InvalidUtf8));
+    }
+
+    #[tokio::test]
+    async fn echoes_close_frame() {
+        let (mut ws, mut client) = socket(None, None);
+        let payload = close_payload(4000, b"done");
+        client
+            .write_all(&client_frame(true, OP_CLOSE, &payload))
+            .await
+            .unwrap();
+
+        let message = ws.recv().await.unwrap().unwrap();
+        let close = CloseFrame {
+            code: 4000,
+            reason: "done".to_string(),
+        };
+        assert_eq!(message, Message::Close(Some(close)));
+        assert_eq!(
+            server_frame(&mut client).await,
+            (OP_CLOSE, close_payload(4000, b""))
+        );
+        assert!(ws.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn closes_going_away_on_shutdown() {
+        let handle = ShutdownHandle::new();
+        let (mut ws, mut client) = socket(Some(handle.signal()), None);
+        handle.shutdown();
+
+        let client = async {
+            let frame = server_frame(&mut client).await;
+            assert_eq!(frame, (OP_CLOSE, close_payload(1001, b"going away")));
+            client
+                .write_all(&client_frame(true, OP_CLOSE, &close_payload(1001, b"")))
+                .await
+                .unwrap();
+        };
+        let (message, ()) = tokio::join!(ws.recv(), client);
+
+        let close = CloseFrame {
+            code: 1001,
+            reason: String::new(),
+        };
+        assert_eq!(message.unwrap().unwrap(), Message::Close(Some(close)));
+        assert!(ws.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn closes_going_away_when_idle() {
+        let (mut ws, mut client) = socket(None, Some(Duration::from_millis(20)));
+
+        // the client never answers the close frame
+        let err = ws.recv().await.unwrap().unwrap_err();
+        assert!(matches!(&err, WebSocketError::Io(err) if err.kind() == io::ErrorKind::TimedOut));
+        let frame = server_frame(&mut client).await;
+        assert_eq!(frame, (OP_CLOSE, close_payload(1001, b"going away")));
+        assert!(ws.recv().await.is_none());
+    }
+}
@@ -5,9 +5,7 @@ pub mod types;
 use std::env;
 
 use crate::core::{
-    logging::Logging,
-    router::HttpRouter,
-    server::{Context, HttpServer},
+    encoding::CompressionPolicy, logging::Logging, router::HttpRouter, server::HttpServer,
 };
 
 #[tokio::main]
@@ -28,9 +26,15 @@ async fn main() {
     router.post("/files/{filename}", api::create_file);
 
     let mut server = HttpServer::new(router);
-    server.set_context(Context { workdir });
+    server.set_workdir(workdir);
+    // tiny bodies such as `/echo/abc` are compressed too, as clients expect
+    server.set_compression(CompressionPolicy {
+        min_size: 0,
+        only_if_smaller: false,
+        ..Default::default()
+    });
     server.enable_logging();
 
     // dbg!(&server);
-    server.listen(4221).await;
+    server.listen("127.0.0.1:4221").await.unwrap();
 }
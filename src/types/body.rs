@@ -0,0 +1,111 @@
+use std::{fmt, io, pin::Pin};
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt, stream::BoxStream};
+use tokio::io::AsyncRead;
+
+pub type BoxReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// The body of an [`HttpResponse`](super::response::HttpResponse).
+///
+/// Besides a buffer held in memory, a body can be produced while it is written to
+/// the connection, from an async reader (a file, a pipe) or from a stream of chunks.
+/// Such bodies are sent with a `Content-Length` when their size is known up front and
+/// with chunked transfer encoding otherwise.
+pub enum Body {
+    Full(Bytes),
+    Reader {
+        reader: BoxReader,
+        length: Option<u64>,
+    },
+    Stream(BoxStream<'static, io::Result<Bytes>>),
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body::Full(Bytes::new())
+    }
+
+    /// A body read from `reader`. When `length` is given, exactly that many bytes are
+    /// sent, otherwise the reader is consumed until EOF.
+    pub fn from_reader(reader: impl AsyncRead + Send + 'static, length: Option<u64>) -> Self {
+        Body::Reader {
+            reader: Box::pin(reader),
+            length,
+        }
+    }
+
+    pub fn from_stream(stream: impl Stream<Item = io::Result<Bytes>> + Send + 'static) -> Self {
+        Body::Stream(stream.boxed())
+    }
+
+    /// The size of the body in bytes, if it is known before writing it.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Full(bytes) => Some(bytes.len() as u64),
+            Body::Reader { length, .. } => *length,
+            Body::Stream(_) => None,
+        }
+    }
+
+    /// Whether the body is known to be empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The body content, if it is held in memory.
+    pub fn as_bytes(&self) -> Option<&Bytes> {
+        match self {
+            Body::Full(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::empty()
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Full(bytes) => f.debug_tuple("Full").field(&bytes.len()).finish(),
+            Body::Reader { length, .. } => {
+                f.debug_struct("Reader").field("length", length).finish()
+            }
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body::Full(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Full(bytes.into())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Full(text.into())
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Body::Full(text.into())
+    }
+}
+
+impl From<&'static [u8]> for Body {
+    fn from(bytes: &'static [u8]) -> Self {
+        Body::Full(bytes.into())
+    }
+}
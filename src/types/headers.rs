@@ -0,0 +1,103 @@
+/// An ordered collection of HTTP header fields.
+///
+/// Field names are matched case-insensitively and keep the casing they were inserted
+/// with. A name may appear several times (`Set-Cookie`, `Via`, ...), in which case
+/// every value is kept in the order it was added.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpHeaders {
+    entries: Vec<(String, String)>,
+}
+
+impl HttpHeaders {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the first value of the field `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).next()
+    }
+
+    /// Returns every value of the field `name`, in insertion order.
+    pub fn get_all<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Checks whether any value of the comma-separated list field `name` contains
+    /// `token`, ignoring case (e.g. `Connection: keep-alive, Upgrade`).
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Sets the field `name` to `value`, replacing any previous values.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Adds `value` to the field `name`, keeping any previous values.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Removes every value of the field `name`.
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Parses an item of a weighted list such as `Accept` or `Accept-Encoding`:
+/// `value[;params][;q=weight]`. Returns the lowercased value and its weight,
+/// 1 by default, or `None` for an empty value or an invalid weight.
+pub fn parse_weighted(item: &str) -> Option<(String, f32)> {
+    let mut parts = item.split(';');
+    let value = parts.next()?.trim().to_ascii_lowercase();
+    if value.is_empty() {
+        return None;
+    }
+
+    let mut q = 1.0;
+    for param in parts {
+        if let Some((key, weight)) = param.split_once('=')
+            && key.trim().eq_ignore_ascii_case("q")
+        {
+            q = weight.trim().parse::<f32>().ok()?;
+            // also refuses NaN
+            if !(0.0..=1.0).contains(&q) {
+                return None;
+            }
+        }
+    }
+    Some((value, q))
+}
@@ -1,4 +1,6 @@
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum HttpRequestMethod {
     GET,
     HEAD,
@@ -11,3 +13,26 @@ pub enum HttpRequestMethod {
     TRACE,
     UNKNOWN,
 }
+
+impl HttpRequestMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpRequestMethod::GET => "GET",
+            HttpRequestMethod::HEAD => "HEAD",
+            HttpRequestMethod::POST => "POST",
+            HttpRequestMethod::PUT => "PUT",
+            HttpRequestMethod::PATCH => "PATCH",
+            HttpRequestMethod::DELETE => "DELETE",
+            HttpRequestMethod::OPTIONS => "OPTIONS",
+            HttpRequestMethod::CONNECT => "CONNECT",
+            HttpRequestMethod::TRACE => "TRACE",
+            HttpRequestMethod::UNKNOWN => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for HttpRequestMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -1,4 +1,7 @@
+pub mod body;
+pub mod headers;
 pub mod method;
 pub mod request;
 pub mod response;
+pub mod sse;
 pub mod status;
@@ -1,34 +1,70 @@
-use super::method::HttpRequestMethod;
-use std::collections::HashMap;
+use super::{headers::HttpHeaders, method::HttpRequestMethod};
+use bytes::Bytes;
+use std::{borrow::Cow, collections::HashMap, str::Utf8Error};
 
 #[derive(Debug, Clone)]
 pub struct HttpRequest {
     pub method: HttpRequestMethod,
+    /// The raw request target, as sent by the client
     pub target: String,
+    /// The percent-decoded path of the target, used for routing
+    pub path: String,
+    pub query: HashMap<String, Vec<String>>,
     pub version: String,
-    pub body: String,
+    pub body: Bytes,
     pub path_params: HashMap<String, String>,
-    pub headers: HashMap<String, String>,
+    pub headers: HttpHeaders,
+    pub trailers: HttpHeaders,
 }
 
-pub struct RequestLine(pub HttpRequestMethod, pub String, pub String);
+pub struct RequestLine {
+    pub method: HttpRequestMethod,
+    pub target: String,
+    pub path: String,
+    pub query: HashMap<String, Vec<String>>,
+    pub version: String,
+}
 
 impl HttpRequest {
     pub fn new(
-        method: HttpRequestMethod,
-        target: String,
-        version: String,
-        body: String,
-        path_params: HashMap<String, String>,
-        headers: HashMap<String, String>,
+        request_line: RequestLine,
+        body: Bytes,
+        headers: HttpHeaders,
+        trailers: HttpHeaders,
     ) -> Self {
         Self {
-            method,
-            target,
-            version,
+            method: request_line.method,
+            target: request_line.target,
+            path: request_line.path,
+            query: request_line.query,
+            version: request_line.version,
             headers,
             body,
-            path_params,
+            path_params: HashMap::new(),
+            trailers,
         }
     }
+
+    /// Returns the first value of the query parameter `key`.
+    pub fn query_param(&self, key: &str) -> Option<&str> {
+        self.query
+            .get(key)
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    }
+
+    /// Returns every value of the query parameter `key`, in the order they were sent.
+    pub fn query_params(&self, key: &str) -> &[String] {
+        self.query.get(key).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Returns the body as text, failing if it is not valid UTF-8.
+    pub fn text(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
+
+    /// Returns the body as text, replacing invalid UTF-8 sequences.
+    pub fn text_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
 }
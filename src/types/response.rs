@@ -1,24 +1,37 @@
-use std::{collections::HashMap, error::Error, fmt::Display};
+use std::{borrow::Cow, error::Error, fmt::Display, future::Future, time::Duration};
 
-use super::status::StatusCode;
+use futures_util::Stream;
+
+use super::{
+    body::Body,
+    headers::HttpHeaders,
+    sse::{Event, encode_events},
+    status::StatusCode,
+};
+use crate::core::upgrade::{OnUpgrade, Upgraded};
 
 #[derive(Debug)]
 pub struct HttpResponse {
     pub protocol: String,
     pub status_code: u32,
-    pub body: String,
+    pub body: Body,
     pub reason: String,
     // pubquery: HashMap<String, String>,
-    pub headers: HashMap<String, String>,
-    pub encoded: Vec<u8>,
+    pub headers: HttpHeaders,
+    /// Sent after the body, which forces chunked transfer encoding
+    pub trailers: HttpHeaders,
+    /// Takes over the connection after a `101 Switching Protocols` response
+    pub upgrade: Option<OnUpgrade>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct HttpResponseBuilder {
     protocol: String,
     status_code: Option<StatusCode>,
-    body: String,
-    headers: HashMap<String, String>,
+    body: Body,
+    headers: HttpHeaders,
+    trailers: HttpHeaders,
+    upgrade: Option<OnUpgrade>,
 }
 
 impl HttpResponseBuilder {
@@ -26,8 +39,10 @@ impl HttpResponseBuilder {
         Self {
             protocol: "HTTP/1.1".to_string(),
             status_code: None,
-            body: String::new(),
-            headers: HashMap::new(),
+            body: Body::empty(),
+            headers: HttpHeaders::new(),
+            trailers: HttpHeaders::new(),
+            upgrade: None,
         }
     }
 
@@ -41,33 +56,71 @@ impl HttpResponseBuilder {
         self
     }
 
-    pub fn file(&mut self, content: Vec<u8>) -> &mut Self {
-        self.body = String::from_utf8(content).unwrap_or(String::new());
+    /// Sends `content` as `application/octet-stream`; set `Content-Type` afterwards
+    /// when the type is known, see [`MimeTypes`](crate::core::mime::MimeTypes).
+    pub fn file(&mut self, content: impl Into<Body>) -> &mut Self {
+        self.body = content.into();
         self.header("Content-Type", "application/octet-stream")
     }
 
-    pub fn body(&mut self, body: String) -> &mut Self {
-        self.body = body;
+    pub fn body(&mut self, body: impl Into<Body>) -> &mut Self {
+        self.body = body.into();
         self
     }
 
-    pub fn json(&mut self, body: String) -> &mut Self {
-        self.body = body;
+    pub fn json(&mut self, body: impl Into<Body>) -> &mut Self {
+        self.body = body.into();
         self.header("Content-Type", "application/json")
     }
 
-    pub fn plain_text(&mut self, body: String) -> &mut Self {
-        self.body = body;
+    pub fn plain_text(&mut self, body: impl Into<Body>) -> &mut Self {
+        self.body = body.into();
         self.header("Content-Type", "text/plain")
     }
 
+    /// Streams `events` as a `text/event-stream` (Server-Sent Events) response. Each
+    /// event is sent as soon as it is produced, and a comment is sent whenever no event
+    /// came for `keep_alive`, so that idle connections are not dropped along the way.
+    /// The response ends with the stream.
+    pub fn event_stream(
+        &mut self,
+        events: impl Stream<Item = Event> + Send + 'static,
+        keep_alive: Option<Duration>,
+    ) -> &mut Self {
+        self.body = Body::from_stream(encode_events(events, keep_alive));
+        self.header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+    }
+
+    /// Sets the header `key`, replacing any value set before.
     pub fn header(&mut self, key: &str, value: &str) -> &mut Self {
-        self.headers.insert(key.to_string(), value.to_string());
+        self.headers.insert(key, value);
         self
     }
 
-    pub fn get(&self) -> Self {
-        self.clone()
+    /// Adds a value to the header `key`, keeping the ones set before (e.g. `Set-Cookie`).
+    pub fn append_header(&mut self, key: &str, value: &str) -> &mut Self {
+        self.headers.append(key, value);
+        self
+    }
+
+    /// Adds a trailer field, sent after the body. Responses with trailers are sent
+    /// with chunked transfer encoding; HTTP/1.0 clients do not receive them.
+    pub fn trailer(&mut self, key: &str, value: &str) -> &mut Self {
+        self.trailers.append(key, value);
+        self
+    }
+
+    /// Switches the connection to another protocol: once this `101 Switching Protocols`
+    /// response is written, the server stops speaking HTTP on the connection and hands
+    /// it to `on_upgrade`. It is ignored on responses with any other status.
+    pub fn on_upgrade<F, Fut>(&mut self, on_upgrade: F) -> &mut Self
+    where
+        F: FnOnce(Upgraded) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.upgrade = Some(OnUpgrade::new(on_upgrade));
+        self
     }
 
     pub fn build(&mut self) -> HttpResponse {
@@ -76,21 +129,20 @@ impl HttpResponseBuilder {
             None => StatusCode::InternalServerError,
         };
 
-        match status {
-            StatusCode::InternalServerError => {
-                self.body = "".to_string();
-                self.headers.clear();
-            }
-            _ => {}
+        if status == StatusCode::InternalServerError {
+            self.body = Body::empty();
+            self.headers.clear();
+            self.trailers.clear();
         }
 
         HttpResponse {
             protocol: self.protocol.to_string(),
             status_code: status as u32,
-            body: self.body.to_string(),
+            body: std::mem::take(&mut self.body),
             reason: status.reason().to_string(),
             headers: self.headers.clone(),
-            encoded: Vec::new(),
+            trailers: self.trailers.clone(),
+            upgrade: self.upgrade.take(),
         }
     }
 }
@@ -112,4 +164,18 @@ impl HttpResponse {
     pub fn builder() -> HttpResponseBuilder {
         HttpResponseBuilder::new()
     }
+
+    /// Returns the body as text, if it is held in memory and is valid UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        self.body
+            .as_bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    /// Returns the body as text, replacing invalid UTF-8 sequences, if it is held in memory.
+    pub fn text_lossy(&self) -> Option<Cow<'_, str>> {
+        self.body
+            .as_bytes()
+            .map(|bytes| String::from_utf8_lossy(bytes))
+    }
 }
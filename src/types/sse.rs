@@ -0,0 +1,125 @@
+use std::{fmt::Write, io, time::Duration};
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt, stream};
+
+/// An event of a `text/event-stream` response, see
+/// [`HttpResponseBuilder::event_stream`](super::response::HttpResponseBuilder::event_stream).
+///
+/// Every field is optional. Multi-line data is sent as several `data:` lines, which
+/// the client joins back with newlines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The event payload.
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// The event type, dispatched to `addEventListener(type)` listeners in browsers.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// The id the client sends back in `Last-Event-ID` when it reconnects.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// How long the client waits before reconnecting once the stream ends.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// A comment, ignored by clients.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Encodes the event in the `text/event-stream` format.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut out = String::new();
+        if let Some(comment) = &self.comment {
+            for line in split_lines(comment) {
+                let _ = writeln!(out, ": {}", line);
+            }
+        }
+        // line breaks would end these single-line fields early
+        if let Some(event) = &self.event {
+            let _ = writeln!(out, "event: {}", single_line(event));
+        }
+        if let Some(id) = &self.id {
+            let _ = writeln!(out, "id: {}", single_line(id));
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(out, "retry: {}", retry.as_millis());
+        }
+        if let Some(data) = &self.data {
+            for line in split_lines(data) {
+                let _ = writeln!(out, "data: {}", line);
+            }
+        }
+        out.push('\n');
+        Bytes::from(out)
+    }
+}
+
+/// Splits `value` on CRLF, CR and LF, which all end a line in an event stream.
+fn split_lines(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split("\r\n")
+        .flat_map(|line| line.split(['\r', '\n']))
+}
+
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+/// Encodes `events` as body chunks, one per event, sending a keep-alive comment
+/// whenever no event was produced for `keep_alive`.
+pub(crate) fn encode_events(
+    events: impl Stream<Item = Event> + Send + 'static,
+    keep_alive: Option<Duration>,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+    stream::unfold(events.boxed(), move |mut events| async move {
+        let chunk = match keep_alive {
+            Some(interval) => match tokio::time::timeout(interval, events.next()).await {
+                Ok(event) => event?.to_bytes(),
+                // proxies tend to drop connections which stay silent for too long
+                Err(_) => Bytes::from_static(b":\n\n"),
+            },
+            None => events.next().await?.to_bytes(),
+        };
+        Some((Ok(chunk), events))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_data_and_comments_on_every_line_break() {
+        let event = Event::new().comment("a\rb").data("x\ry\r\nz\n");
+        assert_eq!(
+            &event.to_bytes()[..],
+            b": a\n: b\ndata: x\ndata: y\ndata: z\ndata: \n\n"
+        );
+    }
+}